@@ -0,0 +1,69 @@
+//! Loopback packet generator for benchmarking the collector.
+//! Sends the same template and data packet in a loop, spread
+//! over several source sockets so that SO_REUSEPORT workers
+//! all get a share.
+//!
+//! ```text
+//! cargo run --release --example flowgen -- 127.0.0.1:2055 1000000 8
+//! ```
+
+use std::env;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::Instant;
+
+/// A template flowset followed by a data flowset with one record
+const PACKET: [u8; 230] = [
+    0x00, 0x09, 0x00, 0x04, 0x63, 0x76, 0x26, 0xee, 0x5a, 0x20, 0x85, 0x54,
+    0x00, 0x01, 0xef, 0xb7, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x68,
+    0x01, 0x01, 0x00, 0x18, 0x00, 0x08, 0x00, 0x04, 0x00, 0x0c, 0x00, 0x04,
+    0x00, 0x0f, 0x00, 0x04, 0x00, 0x12, 0x00, 0x04, 0x00, 0x0a, 0x00, 0x02,
+    0x00, 0x0e, 0x00, 0x02, 0x00, 0x02, 0x00, 0x04, 0x00, 0x01, 0x00, 0x04,
+    0x00, 0x16, 0x00, 0x04, 0x00, 0x15, 0x00, 0x04, 0x00, 0x07, 0x00, 0x02,
+    0x00, 0x0b, 0x00, 0x02, 0x00, 0x59, 0x00, 0x01, 0x00, 0x06, 0x00, 0x01,
+    0x00, 0x04, 0x00, 0x01, 0x00, 0x05, 0x00, 0x01, 0x00, 0x3c, 0x00, 0x01,
+    0x00, 0x3d, 0x00, 0x01, 0x00, 0x20, 0x00, 0x02, 0x00, 0x10, 0x00, 0x04,
+    0x00, 0x11, 0x00, 0x04, 0x00, 0x09, 0x00, 0x01, 0x00, 0x0d, 0x00, 0x01,
+    0x00, 0x63, 0x00, 0x04, 0x00, 0x01, 0x00, 0x18, 0x01, 0x00, 0x00, 0x04,
+    0x00, 0x08, 0x00, 0x01, 0x00, 0x04, 0x00, 0x23, 0x00, 0x01, 0x00, 0x22,
+    0x00, 0x04, 0x00, 0x00, 0x01, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00,
+    0x01, 0x00, 0x00, 0x03, 0xe8, 0x00, 0x00, 0x00, 0x01, 0x01, 0x00, 0x42,
+    0xb9, 0xa7, 0xc4, 0x7f, 0xb9, 0xa7, 0xc4, 0x7e, 0x00, 0x00, 0x00, 0x00,
+    0xb9, 0xa7, 0xc4, 0x7e, 0x00, 0x00, 0x01, 0x14, 0x00, 0x00, 0x00, 0x01,
+    0x00, 0x00, 0x00, 0x34, 0x63, 0x75, 0x04, 0xee, 0x63, 0x75, 0x04, 0xee,
+    0xc0, 0x00, 0x0e, 0xc8, 0x42, 0x00, 0x11, 0xc0, 0x04, 0x01, 0x00, 0x00,
+    0x00, 0x03, 0x28, 0xc4, 0x00, 0x03, 0x28, 0xc4, 0x20, 0x1f, 0x00, 0x00,
+    0x00, 0x00,
+];
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let target: SocketAddr = args
+        .next()
+        .unwrap_or_else(|| "127.0.0.1:2055".to_owned())
+        .parse()
+        .expect("target address");
+    let packets: u64 = args.next().map_or(1_000_000, |n| n.parse().unwrap());
+    let sources: usize = args.next().map_or(4, |n| n.parse().unwrap());
+
+    let local = if target.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    };
+    let sockets: Vec<UdpSocket> = (0..sources.max(1))
+        .map(|_| UdpSocket::bind(local).unwrap())
+        .collect();
+
+    let start = Instant::now();
+    for i in 0..packets {
+        let socket = &sockets[i as usize % sockets.len()];
+        socket.send_to(&PACKET, target).unwrap();
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+    println!(
+        "sent {} packets in {:.3}s, {:.0} packets/s",
+        packets,
+        elapsed,
+        packets as f64 / elapsed
+    );
+}
@@ -0,0 +1,113 @@
+use serde::de::{self, Deserializer};
+use serde::Deserialize;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// An IPv4 or IPv6 network such as `192.0.2.0/24`. A bare
+/// address is a network of that address alone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+fn mask_v4(prefix: u8) -> u32 {
+    u32::MAX.checked_shl(32 - u32::from(prefix)).unwrap_or(0)
+}
+
+fn mask_v6(prefix: u8) -> u128 {
+    u128::MAX.checked_shl(128 - u32::from(prefix)).unwrap_or(0)
+}
+
+impl Cidr {
+    /// `None` if `prefix` is longer than the address. The host
+    /// bits of `addr` are cleared, and networks within
+    /// `::ffff:0:0/96` are turned into the IPv4 networks they
+    /// map, as exporters are matched by their IPv4 address.
+    pub fn new(addr: IpAddr, prefix: u8) -> Option<Self> {
+        let bits = if addr.is_ipv4() { 32 } else { 128 };
+        if prefix > bits {
+            return None;
+        }
+        let (addr, prefix) = match addr.to_canonical() {
+            IpAddr::V4(v4) if addr.is_ipv6() && prefix >= 96 => {
+                (IpAddr::V4(v4), prefix - 96)
+            }
+            _ => (addr, prefix),
+        };
+        let addr = match addr {
+            IpAddr::V4(v4) => {
+                IpAddr::V4((u32::from(v4) & mask_v4(prefix)).into())
+            }
+            IpAddr::V6(v6) => {
+                IpAddr::V6((u128::from(v6) & mask_v6(prefix)).into())
+            }
+        };
+        Some(Cidr { addr, prefix })
+    }
+
+    pub fn contains(&self, addr: &IpAddr) -> bool {
+        match (self.addr, addr.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(addr)) => {
+                u32::from(net) == u32::from(addr) & mask_v4(self.prefix)
+            }
+            (IpAddr::V6(net), IpAddr::V6(addr)) => {
+                u128::from(net) == u128::from(addr) & mask_v6(self.prefix)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|_| "Invalid address")?;
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse().map_err(|_| "Invalid prefix")?,
+            None if addr.is_ipv4() => 32,
+            None => 128,
+        };
+        Cidr::new(addr, prefix).ok_or("Invalid prefix")
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+impl<'de> Deserialize<'de> for Cidr {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse()
+            .map_err(|e| de::Error::custom(format!("{}: {}", s, e)))
+    }
+}
+
+/// Which exporters a `Parser` accepts packets from. An
+/// exporter is accepted unless it is in `deny`, and when
+/// `allow` is not empty, only if it is in `allow`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExporterAcl {
+    pub allow: Vec<Cidr>,
+    pub deny: Vec<Cidr>,
+}
+
+impl ExporterAcl {
+    pub fn allows(&self, exporter: &IpAddr) -> bool {
+        !self.deny.iter().any(|n| n.contains(exporter))
+            && (self.allow.is_empty()
+                || self.allow.iter().any(|n| n.contains(exporter)))
+    }
+}
@@ -0,0 +1,268 @@
+use crate::csv::Column;
+use crate::formaters::{read_uint, KeyStyle};
+use crate::templates::{field_key, field_kind, FieldKind};
+use crate::DataFlowset;
+use arrow_array::builder::{
+    FixedSizeBinaryBuilder, StringBuilder, TimestampMillisecondBuilder,
+    UInt64Builder,
+};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{ArrowError, DataType, Field, Schema, SchemaRef, TimeUnit};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How IP addresses are stored in Arrow columns
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpRepr {
+    /// Textual form, e.g. `"192.168.1.1"`
+    String,
+    /// Network order bytes: 4 bytes for IPv4 fields, 16 bytes
+    /// for IPv6 fields and the exporter address (IPv4 mapped)
+    Binary,
+}
+
+#[derive(Debug, Clone)]
+pub struct ArrowConfig {
+    /// Naming of the columns
+    pub keys: KeyStyle,
+    pub ip_repr: IpRepr,
+    /// Rows per batch
+    pub max_rows: usize,
+    /// Age of the oldest row after which a batch is flushed
+    pub max_age: Duration,
+}
+
+impl Default for ArrowConfig {
+    fn default() -> Self {
+        ArrowConfig {
+            keys: KeyStyle::SnakeCase,
+            ip_repr: IpRepr::String,
+            max_rows: 8192,
+            max_age: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Arrow type of a column, derived from the field registry
+pub fn column_type(column: Column, ip_repr: IpRepr) -> DataType {
+    let kind = match column {
+        Column::SourceIp => FieldKind::Ipv6,
+        Column::Field(f) => field_kind(f),
+    };
+    match (kind, ip_repr) {
+        (FieldKind::Ipv4, IpRepr::Binary) => DataType::FixedSizeBinary(4),
+        (FieldKind::Ipv6, IpRepr::Binary) => DataType::FixedSizeBinary(16),
+        (FieldKind::Mac, _) => DataType::FixedSizeBinary(6),
+        (FieldKind::Ipv4, IpRepr::String)
+        | (FieldKind::Ipv6, IpRepr::String)
+        | (FieldKind::Text, _) => DataType::Utf8,
+        (FieldKind::Uptime, _) => {
+            DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into()))
+        }
+        (FieldKind::Unsigned, _) => DataType::UInt64,
+    }
+}
+
+/// Build the schema for a set of columns
+pub fn schema(columns: &[Column], config: &ArrowConfig) -> Schema {
+    let fields: Vec<Field> = columns
+        .iter()
+        .map(|c| {
+            let name = match c {
+                Column::SourceIp => "source_ip".into(),
+                Column::Field(f) => field_key(*f, config.keys),
+            };
+            Field::new(name, column_type(*c, config.ip_repr), true)
+        })
+        .collect();
+    Schema::new(fields)
+}
+
+enum ColumnBuilder {
+    UInt64(UInt64Builder),
+    Binary(FixedSizeBinaryBuilder, usize),
+    Utf8(StringBuilder),
+    Timestamp(TimestampMillisecondBuilder),
+}
+
+impl ColumnBuilder {
+    fn new(data_type: &DataType, capacity: usize) -> Self {
+        match data_type {
+            DataType::FixedSizeBinary(n) => ColumnBuilder::Binary(
+                FixedSizeBinaryBuilder::with_capacity(capacity, *n),
+                *n as usize,
+            ),
+            DataType::Utf8 => ColumnBuilder::Utf8(
+                StringBuilder::with_capacity(capacity, capacity * 16),
+            ),
+            DataType::Timestamp(..) => ColumnBuilder::Timestamp(
+                TimestampMillisecondBuilder::with_capacity(capacity)
+                    .with_timezone("UTC"),
+            ),
+            _ => ColumnBuilder::UInt64(UInt64Builder::with_capacity(capacity)),
+        }
+    }
+
+    fn append_null(&mut self) {
+        match self {
+            ColumnBuilder::UInt64(b) => b.append_null(),
+            ColumnBuilder::Binary(b, _) => b.append_null(),
+            ColumnBuilder::Utf8(b) => b.append_null(),
+            ColumnBuilder::Timestamp(b) => b.append_null(),
+        }
+    }
+
+    fn append_ip(&mut self, ip: IpAddr) {
+        match self {
+            ColumnBuilder::Utf8(b) => b.append_value(ip.to_string()),
+            ColumnBuilder::Binary(b, 16) => {
+                let v6 = match ip {
+                    IpAddr::V4(v4) => v4.to_ipv6_mapped(),
+                    IpAddr::V6(v6) => v6,
+                };
+                // Width always matches so this cannot fail
+                let _ = b.append_value(v6.octets());
+            }
+            _ => self.append_null(),
+        }
+    }
+
+    fn append_field(
+        &mut self,
+        kind: FieldKind,
+        value: &[u8],
+        set: &DataFlowset,
+    ) {
+        match (self, kind) {
+            (ColumnBuilder::UInt64(b), _) => b.append_option(read_uint(value)),
+            (ColumnBuilder::Timestamp(b), _) => b.append_option(
+                read_uint(value).map(|v| set.uptime_to_millis(v as u32) as i64),
+            ),
+            (ColumnBuilder::Binary(b, width), _) => {
+                if value.len() == *width {
+                    let _ = b.append_value(value);
+                } else {
+                    b.append_null()
+                }
+            }
+            (ColumnBuilder::Utf8(b), FieldKind::Ipv4) if value.len() == 4 => {
+                let mut octets = [0; 4];
+                octets.copy_from_slice(value);
+                b.append_value(Ipv4Addr::from(octets).to_string())
+            }
+            (ColumnBuilder::Utf8(b), FieldKind::Ipv6) if value.len() == 16 => {
+                let mut octets = [0; 16];
+                octets.copy_from_slice(value);
+                b.append_value(Ipv6Addr::from(octets).to_string())
+            }
+            (ColumnBuilder::Utf8(b), FieldKind::Text) => {
+                let end =
+                    value.iter().position(|c| *c == 0).unwrap_or(value.len());
+                b.append_value(String::from_utf8_lossy(&value[..end]))
+            }
+            (ColumnBuilder::Utf8(b), _) => b.append_null(),
+        }
+    }
+
+    fn finish(&mut self) -> ArrayRef {
+        match self {
+            ColumnBuilder::UInt64(b) => Arc::new(b.finish()),
+            ColumnBuilder::Binary(b, _) => Arc::new(b.finish()),
+            ColumnBuilder::Utf8(b) => Arc::new(b.finish()),
+            ColumnBuilder::Timestamp(b) => Arc::new(b.finish()),
+        }
+    }
+}
+
+/// Collects flowsets into Arrow `RecordBatch`es with a fixed
+/// schema. A batch is handed out once it reaches
+/// `max_rows` rows or its oldest row is `max_age` old.
+pub struct ArrowBatcher {
+    columns: Vec<Column>,
+    schema: SchemaRef,
+    builders: Vec<ColumnBuilder>,
+    config: ArrowConfig,
+    rows: usize,
+    started: Option<Instant>,
+}
+
+impl ArrowBatcher {
+    pub fn new(columns: Vec<Column>, config: ArrowConfig) -> Self {
+        let schema = Arc::new(schema(&columns, &config));
+        let builders = schema
+            .fields()
+            .iter()
+            .map(|f| ColumnBuilder::new(f.data_type(), config.max_rows))
+            .collect();
+        ArrowBatcher {
+            columns,
+            schema,
+            builders,
+            config,
+            rows: 0,
+            started: None,
+        }
+    }
+
+    pub fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    /// Number of rows waiting in the current batch
+    pub fn len(&self) -> usize {
+        self.rows
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows == 0
+    }
+
+    /// Add a flowset, returning a batch if this filled it up
+    /// or the batch timed out
+    pub fn push(
+        &mut self,
+        set: &DataFlowset,
+    ) -> Result<Option<RecordBatch>, ArrowError> {
+        for (column, builder) in self.columns.iter().zip(&mut self.builders) {
+            match column {
+                Column::SourceIp => match set.source_ip {
+                    Some(ip) => builder.append_ip(*ip),
+                    None => builder.append_null(),
+                },
+                Column::Field(f) => match set.records.get(f) {
+                    Some(v) => builder.append_field(field_kind(*f), v, set),
+                    None => builder.append_null(),
+                },
+            }
+        }
+        self.rows += 1;
+        self.started.get_or_insert_with(Instant::now);
+
+        if self.rows >= self.config.max_rows {
+            return self.flush();
+        }
+        self.poll()
+    }
+
+    /// Flush the batch if its oldest row is older than
+    /// `max_age`. Meant to be called periodically when the
+    /// traffic is low.
+    pub fn poll(&mut self) -> Result<Option<RecordBatch>, ArrowError> {
+        match self.started {
+            Some(t) if t.elapsed() >= self.config.max_age => self.flush(),
+            _ => Ok(None),
+        }
+    }
+
+    /// Hand out whatever has been collected so far
+    pub fn flush(&mut self) -> Result<Option<RecordBatch>, ArrowError> {
+        if self.rows == 0 {
+            return Ok(None);
+        }
+        let arrays = self.builders.iter_mut().map(|b| b.finish()).collect();
+        self.rows = 0;
+        self.started = None;
+        RecordBatch::try_new(self.schema.clone(), arrays).map(Some)
+    }
+}
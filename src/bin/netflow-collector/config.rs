@@ -0,0 +1,175 @@
+use netflow_v9::{
+    Cidr, ExporterAcl, StaticTemplate, StaticTemplatePolicy, TemplateLimits,
+};
+use serde::Deserialize;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::{fmt, fs};
+
+/// Collector configuration, read from a TOML file
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Addresses to receive export packets on
+    pub listen: Vec<SocketAddr>,
+    /// Size of the receive buffer, the largest datagram accepted
+    pub buffer_size: usize,
+    /// Sockets and threads per listen address. With more than
+    /// one the sockets share the address with SO_REUSEPORT.
+    pub workers: usize,
+    /// Datagrams read per system call (Linux `recvmmsg`)
+    pub batch_size: usize,
+    /// Networks export packets are accepted from, all when
+    /// empty
+    pub allow_exporters: Vec<Cidr>,
+    /// Networks export packets are dropped from, before
+    /// `allow_exporters` is looked at
+    pub deny_exporters: Vec<Cidr>,
+    /// Exporters whose templates can't be redefined once
+    /// received
+    pub pin_exporters: Vec<Cidr>,
+    /// Alert when a template is redefined more than this many
+    /// times within `redefinition_window` seconds, 0 never
+    pub max_redefinitions: u32,
+    pub redefinition_window: u64,
+    /// File the templates are saved to and restored from on
+    /// startup, so that records can be decoded right away
+    pub template_cache: Option<PathBuf>,
+    /// Seconds between two saves of the templates
+    pub template_save_interval: u64,
+    /// Saved templates older than this many seconds are not
+    /// restored
+    pub template_max_age: u64,
+    /// Seconds after which a template the exporter did not
+    /// refresh is removed, 0 to keep templates until replaced
+    pub template_timeout: u64,
+    /// Bounds on the templates kept, unlimited by default
+    pub max_templates: Option<usize>,
+    pub max_template_bytes: Option<usize>,
+    pub max_templates_per_exporter: Option<usize>,
+    pub max_template_bytes_per_exporter: Option<usize>,
+    /// Templates of further exporters are rejected
+    pub max_exporters: Option<usize>,
+    /// Bytes of data flowsets kept while waiting for their
+    /// template, 0 to drop them right away
+    pub pending_bytes: usize,
+    /// Seconds a data flowset waits for its template at most
+    pub pending_age: u64,
+    /// Whether templates received from the exporters replace
+    /// the static ones (`override`) or are only checked against
+    /// them (`verify`)
+    pub static_templates: StaticTemplatePolicy,
+    /// Templates defined ahead of time
+    #[serde(rename = "template")]
+    pub templates: Vec<StaticTemplate>,
+    #[serde(rename = "output")]
+    pub outputs: Vec<OutputConfig>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let mut listen = Vec::new();
+        for port in [2055, 9995] {
+            listen
+                .push(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port));
+            listen
+                .push(SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), port));
+        }
+        Config {
+            listen,
+            buffer_size: 65535,
+            workers: 1,
+            batch_size: 32,
+            allow_exporters: Vec::new(),
+            deny_exporters: Vec::new(),
+            pin_exporters: Vec::new(),
+            max_redefinitions: 0,
+            redefinition_window: 60,
+            template_cache: None,
+            template_save_interval: 60,
+            template_max_age: 3600,
+            template_timeout: 0,
+            max_templates: None,
+            max_template_bytes: None,
+            max_templates_per_exporter: None,
+            max_template_bytes_per_exporter: None,
+            max_exporters: None,
+            pending_bytes: 1 << 20,
+            pending_age: 30,
+            static_templates: StaticTemplatePolicy::default(),
+            templates: Vec::new(),
+            outputs: vec![OutputConfig::Stdout {
+                format: default_format(),
+            }],
+        }
+    }
+}
+
+/// Where the records go. `format` is `json`, `ecs`, one of the
+/// text formats (`line`, `long`, `extended`, `fmt:<format>`)
+/// or, for `udp` only, `raw` to forward the export packets
+/// untouched.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum OutputConfig {
+    Stdout {
+        #[serde(default = "default_format")]
+        format: String,
+    },
+    File {
+        path: PathBuf,
+        #[serde(default = "default_format")]
+        format: String,
+    },
+    Udp {
+        address: SocketAddr,
+        #[serde(default = "default_format")]
+        format: String,
+    },
+}
+
+fn default_format() -> String {
+    "json".to_owned()
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, std::io::Error),
+    Toml(PathBuf, toml::de::Error),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            ConfigError::Toml(path, e) => {
+                write!(f, "{}: {}", path.display(), e)
+            }
+        }
+    }
+}
+
+impl Config {
+    pub fn exporter_acl(&self) -> ExporterAcl {
+        ExporterAcl {
+            allow: self.allow_exporters.clone(),
+            deny: self.deny_exporters.clone(),
+        }
+    }
+
+    pub fn template_limits(&self) -> TemplateLimits {
+        TemplateLimits {
+            max_templates: self.max_templates,
+            max_bytes: self.max_template_bytes,
+            max_templates_per_exporter: self.max_templates_per_exporter,
+            max_bytes_per_exporter: self.max_template_bytes_per_exporter,
+            max_exporters: self.max_exporters,
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Config, ConfigError> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| ConfigError::Io(path.to_owned(), e))?;
+        toml::from_str(&text).map_err(|e| ConfigError::Toml(path.to_owned(), e))
+    }
+}
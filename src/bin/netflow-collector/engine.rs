@@ -0,0 +1,214 @@
+//! Datagram receive loop. On Linux datagrams are read in
+//! batches with `recvmmsg`, elsewhere one at a time.
+
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+
+/// Bind a UDP socket. With `reuse_port` several sockets can be
+/// bound to the same address and the kernel spreads the
+/// exporters over them, always sending the packets of an
+/// exporter to the same socket.
+pub fn bind(address: SocketAddr, reuse_port: bool) -> io::Result<UdpSocket> {
+    let socket = Socket::new(
+        Domain::for_address(address),
+        Type::DGRAM,
+        Some(Protocol::UDP),
+    )?;
+    // Leave IPv4 to its own socket so that both can use the port
+    if address.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    if reuse_port {
+        #[cfg(unix)]
+        socket.set_reuse_port(true)?;
+        #[cfg(not(unix))]
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "several workers per address need SO_REUSEPORT",
+        ));
+    }
+    socket.bind(&address.into())?;
+    Ok(socket.into())
+}
+
+/// Receives up to `batch_size` datagrams at a time into
+/// buffers of its own
+pub struct Receiver {
+    socket: UdpSocket,
+    buffer_size: usize,
+    buffers: Vec<u8>,
+    /// Offset, length and source of the datagrams of the last
+    /// batch
+    datagrams: Vec<(usize, usize, SocketAddr)>,
+    /// Datagrams dropped for not fitting in a buffer
+    truncated: u64,
+    #[cfg(target_os = "linux")]
+    headers: Headers,
+}
+
+/// `recvmmsg` headers pointing into the buffers of a receiver.
+/// The buffers are never resized, so the headers are built once.
+#[cfg(target_os = "linux")]
+struct Headers {
+    msgs: Vec<libc::mmsghdr>,
+    addrs: Vec<libc::sockaddr_storage>,
+    // Pointed to by `msgs`
+    _iovecs: Vec<libc::iovec>,
+}
+
+// Safety: the pointers only refer to heap allocations owned by
+// the receiver, which move along with it
+#[cfg(target_os = "linux")]
+unsafe impl Send for Headers {}
+
+#[cfg(target_os = "linux")]
+impl Headers {
+    fn new(buffers: &mut [u8], buffer_size: usize) -> Self {
+        use std::mem;
+
+        let mut iovecs: Vec<libc::iovec> = buffers
+            .chunks_mut(buffer_size)
+            .map(|b| libc::iovec {
+                iov_base: b.as_mut_ptr() as *mut libc::c_void,
+                iov_len: b.len(),
+            })
+            .collect();
+        // sockaddr_storage is plain data, all zeroes is valid
+        let mut addrs: Vec<libc::sockaddr_storage> =
+            vec![unsafe { mem::zeroed() }; iovecs.len()];
+        let msgs = iovecs
+            .iter_mut()
+            .zip(addrs.iter_mut())
+            .map(|(iov, addr)| {
+                // msghdr has private padding fields on some
+                // targets, so start from zeroes
+                let mut hdr: libc::msghdr = unsafe { mem::zeroed() };
+                hdr.msg_name = addr as *mut _ as *mut libc::c_void;
+                hdr.msg_iov = iov;
+                hdr.msg_iovlen = 1;
+                libc::mmsghdr {
+                    msg_hdr: hdr,
+                    msg_len: 0,
+                }
+            })
+            .collect();
+        Headers {
+            msgs,
+            addrs,
+            _iovecs: iovecs,
+        }
+    }
+}
+
+impl Receiver {
+    pub fn new(
+        socket: UdpSocket,
+        batch_size: usize,
+        buffer_size: usize,
+    ) -> Self {
+        let batch_size = batch_size.max(1);
+        #[allow(unused_mut)]
+        let mut buffers = vec![0; batch_size * buffer_size];
+        Receiver {
+            socket,
+            buffer_size,
+            #[cfg(target_os = "linux")]
+            headers: Headers::new(&mut buffers, buffer_size),
+            buffers,
+            datagrams: Vec::with_capacity(batch_size),
+            truncated: 0,
+        }
+    }
+
+    /// Number of datagrams in the last batch
+    pub fn len(&self) -> usize {
+        self.datagrams.len()
+    }
+
+    /// Datagram `i` of the last batch and its source address
+    pub fn datagram(&self, i: usize) -> (&[u8], SocketAddr) {
+        let (start, len, source) = self.datagrams[i];
+        (&self.buffers[start..start + len], source)
+    }
+
+    /// Number of datagrams dropped so far for being larger than
+    /// the buffer size
+    pub fn truncated(&self) -> u64 {
+        self.truncated
+    }
+
+    /// Wait for at least one datagram and read those already
+    /// queued, up to the batch size
+    #[cfg(target_os = "linux")]
+    pub fn recv(&mut self) -> io::Result<usize> {
+        use std::os::unix::io::AsRawFd;
+        use std::{mem, ptr};
+
+        let msgs = &mut self.headers.msgs;
+        // The kernel writes back the address length and flags
+        for msg in msgs.iter_mut() {
+            msg.msg_hdr.msg_namelen =
+                mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+            msg.msg_hdr.msg_flags = 0;
+        }
+
+        let received = loop {
+            // Safety: every header points to a live buffer and
+            // address of the lengths it declares
+            let n = unsafe {
+                libc::recvmmsg(
+                    self.socket.as_raw_fd(),
+                    msgs.as_mut_ptr(),
+                    msgs.len() as libc::c_uint,
+                    libc::MSG_WAITFORONE,
+                    ptr::null_mut(),
+                )
+            };
+            if n >= 0 {
+                break n as usize;
+            }
+            let error = io::Error::last_os_error();
+            if error.kind() != io::ErrorKind::Interrupted {
+                return Err(error);
+            }
+        };
+
+        self.datagrams.clear();
+        for (i, (msg, addr)) in msgs
+            .iter()
+            .zip(&self.headers.addrs)
+            .take(received)
+            .enumerate()
+        {
+            // Only part of the datagram was read
+            if msg.msg_hdr.msg_flags & libc::MSG_TRUNC != 0 {
+                self.truncated += 1;
+                continue;
+            }
+            // Safety: the kernel filled in a socket address of
+            // `msg_namelen` bytes
+            let source = unsafe {
+                SockAddr::new(*addr, msg.msg_hdr.msg_namelen).as_socket()
+            };
+            if let Some(source) = source {
+                let start = i * self.buffer_size;
+                self.datagrams.push((start, msg.msg_len as usize, source));
+            }
+        }
+        Ok(self.datagrams.len())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn recv(&mut self) -> io::Result<usize> {
+        let (len, source) = loop {
+            match self.socket.recv_from(&mut self.buffers[..self.buffer_size]) {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                result => break result?,
+            }
+        };
+        self.datagrams.clear();
+        self.datagrams.push((0, len, source));
+        Ok(1)
+    }
+}
@@ -0,0 +1,273 @@
+//! NetFlow v9 collector. Receives export packets over UDP and
+//! writes the decoded records to the configured outputs.
+//!
+//! ```text
+//! netflow-collector [--config <file.toml>]
+//! ```
+//!
+//! Without a configuration file it listens on port 2055 and
+//! 9995 over IPv4 and IPv6 and prints JSON records on stdout.
+
+mod config;
+mod engine;
+mod output;
+
+use config::Config;
+use engine::Receiver;
+use netflow_v9::{
+    DataFlowset, Parser, PendingLimits, SharedTemplateCache, TemplateSnapshot,
+};
+use output::{Encoded, Output};
+use std::net::{IpAddr, UdpSocket};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use std::{env, io, process, thread};
+
+fn usage() -> ! {
+    eprintln!("usage: netflow-collector [--config <file.toml>]");
+    process::exit(2)
+}
+
+fn fail(message: impl std::fmt::Display) -> ! {
+    eprintln!("netflow-collector: {}", message);
+    process::exit(1)
+}
+
+/// Receive loop of a worker. Each worker parses the packets of
+/// its socket with a parser of its own, on top of the template
+/// cache shared by all workers. The outputs are shared too,
+/// records are encoded by the worker and each output is locked
+/// once per batch to write them.
+fn work(
+    socket: UdpSocket,
+    config: &Config,
+    templates: SharedTemplateCache,
+    outputs: &[Output],
+) {
+    let mut receiver =
+        Receiver::new(socket, config.batch_size, config.buffer_size);
+    let mut parser = Parser::with_shared_cache(templates);
+    parser.set_static_policy(config.static_templates);
+    for template in &config.templates {
+        parser.add_static_template(template);
+    }
+    if config.template_timeout > 0 {
+        let timeout = Duration::from_secs(config.template_timeout);
+        parser.set_template_timeout(Some(timeout));
+    }
+    if config.pending_bytes > 0 {
+        parser.set_pending_limits(PendingLimits {
+            max_bytes: config.pending_bytes,
+            max_age: Duration::from_secs(config.pending_age),
+        });
+    }
+    parser.pin_exporters(config.pin_exporters.clone());
+    if config.max_redefinitions > 0 {
+        let window = Duration::from_secs(config.redefinition_window);
+        parser.set_redefinition_limit(config.max_redefinitions, window);
+    }
+    let acl = config.exporter_acl();
+    // Packets from denied exporters are counted rather than
+    // logged one by one
+    let mut denied = 0u64;
+    let mut denied_reported = Instant::now();
+    // Likewise for datagrams dropped by the receiver
+    let mut truncated = 0u64;
+    let mut truncated_reported = Instant::now();
+    let mut exporters: Vec<IpAddr> = Vec::with_capacity(config.batch_size);
+    let mut encoded: Vec<Encoded> =
+        outputs.iter().map(|_| Encoded::default()).collect();
+    loop {
+        if let Err(e) = receiver.recv() {
+            // Such as an ICMP error queued on the socket, keep
+            // receiving without spinning on a lasting one
+            eprintln!("netflow-collector: receive: {}", e);
+            thread::sleep(Duration::from_millis(100));
+            continue;
+        }
+        exporters.clear();
+        exporters.extend(
+            (0..receiver.len())
+                .map(|i| receiver.datagram(i).1.ip().to_canonical()),
+        );
+
+        let mut batch: Vec<(&[u8], Vec<DataFlowset>)> =
+            Vec::with_capacity(receiver.len());
+        for (i, exporter) in exporters.iter().enumerate() {
+            let (packet, source) = receiver.datagram(i);
+            if !acl.allows(exporter) {
+                denied += 1;
+                continue;
+            }
+            let sets = match parser.parse_netflow_packet(packet, exporter) {
+                Ok(sets) => sets,
+                Err(e) => {
+                    eprintln!("netflow-collector: {}: {}", source, e);
+                    Vec::new()
+                }
+            };
+            batch.push((packet, sets));
+        }
+        for event in parser.drain_events() {
+            if event.is_alert() {
+                eprintln!("netflow-collector: alert: {}", event);
+            } else {
+                eprintln!("netflow-collector: {}", event);
+            }
+        }
+        if denied > 0 && denied_reported.elapsed() >= Duration::from_secs(60) {
+            eprintln!(
+                "netflow-collector: {} packets from denied exporters",
+                denied
+            );
+            denied = 0;
+            denied_reported = Instant::now();
+        }
+        if receiver.truncated() > truncated
+            && truncated_reported.elapsed() >= Duration::from_secs(60)
+        {
+            eprintln!(
+                "netflow-collector: {} datagrams larger than the buffer size",
+                receiver.truncated() - truncated
+            );
+            truncated = receiver.truncated();
+            truncated_reported = Instant::now();
+        }
+        // Records of flowsets that waited for their template.
+        // Their packets were forwarded to raw outputs already.
+        let replayed = parser.take_replayed();
+        let replayed: Vec<DataFlowset> =
+            replayed.iter().map(|r| r.flowset()).collect();
+
+        for (output, encoded) in outputs.iter().zip(&mut encoded) {
+            encoded.clear();
+            for (packet, sets) in &batch {
+                if sets.is_empty() && !output.is_raw() {
+                    continue;
+                }
+                if let Err(e) = output.encode(packet, sets, encoded) {
+                    eprintln!("netflow-collector: output: {}", e);
+                }
+            }
+            if !output.is_raw() {
+                if let Err(e) = output.encode(&[], &replayed, encoded) {
+                    eprintln!("netflow-collector: output: {}", e);
+                }
+            }
+            if encoded.is_empty() {
+                continue;
+            }
+            if let Err(e) = output.write(encoded) {
+                eprintln!("netflow-collector: output: {}", e);
+            }
+        }
+    }
+}
+
+/// Restore the templates saved by a previous run. A missing
+/// file is not an error, there is none on the first run.
+fn restore_templates(
+    path: &Path,
+    max_age: Duration,
+    templates: &mut SharedTemplateCache,
+) {
+    match TemplateSnapshot::load(path) {
+        Ok(snapshot) => {
+            let restored = snapshot.restore(templates, Some(max_age));
+            eprintln!(
+                "netflow-collector: restored {} of {} templates from {}",
+                restored,
+                snapshot.len(),
+                path.display()
+            );
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => eprintln!("netflow-collector: {}: {}", path.display(), e),
+    }
+}
+
+/// Save the templates every `interval`
+fn save_templates(
+    path: &Path,
+    interval: Duration,
+    templates: &SharedTemplateCache,
+) {
+    loop {
+        thread::sleep(interval);
+        if let Err(e) = TemplateSnapshot::capture(templates).save(path) {
+            eprintln!("netflow-collector: {}: {}", path.display(), e);
+        }
+    }
+}
+
+fn main() {
+    let mut config_path: Option<PathBuf> = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-c" | "--config" => match args.next() {
+                Some(path) => config_path = Some(path.into()),
+                None => usage(),
+            },
+            _ => usage(),
+        }
+    }
+    let config = match config_path {
+        Some(path) => Config::load(&path).unwrap_or_else(|e| fail(e)),
+        None => Config::default(),
+    };
+    let config = Arc::new(config);
+
+    let outputs: Vec<Output> = config
+        .outputs
+        .iter()
+        .map(Output::open)
+        .collect::<io::Result<_>>()
+        .unwrap_or_else(|e| fail(e));
+    let outputs = Arc::new(outputs);
+
+    let mut templates =
+        SharedTemplateCache::with_limits(config.template_limits());
+    if let Some(path) = &config.template_cache {
+        let max_age = Duration::from_secs(config.template_max_age);
+        restore_templates(path, max_age, &mut templates);
+        let path = path.clone();
+        let interval =
+            Duration::from_secs(config.template_save_interval.max(1));
+        let templates = templates.clone();
+        thread::spawn(move || save_templates(&path, interval, &templates));
+    }
+    let workers = config.workers.max(1);
+    let mut handles = Vec::new();
+    for address in &config.listen {
+        let address = *address;
+        for _ in 0..workers {
+            let socket = engine::bind(address, workers > 1)
+                .unwrap_or_else(|e| fail(format!("{}: {}", address, e)));
+            let config = config.clone();
+            let templates = templates.clone();
+            let outputs = outputs.clone();
+            handles.push(thread::spawn(move || loop {
+                // Restart a worker that panicked rather than leave
+                // its socket without a listener
+                let socket = socket
+                    .try_clone()
+                    .unwrap_or_else(|e| fail(format!("{}: {}", address, e)));
+                let templates = templates.clone();
+                let run = panic::catch_unwind(AssertUnwindSafe(|| {
+                    work(socket, &config, templates, &outputs)
+                }));
+                if run.is_ok() {
+                    break;
+                }
+                eprintln!("netflow-collector: {}: worker restarted", address);
+                thread::sleep(Duration::from_secs(1));
+            }));
+        }
+    }
+    for handle in handles {
+        let _ = handle.join();
+    }
+}
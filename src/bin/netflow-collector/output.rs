@@ -0,0 +1,182 @@
+use crate::config::OutputConfig;
+use netflow_v9::{DataFlowset, TextFormat};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::net::{SocketAddr, UdpSocket};
+use std::ops::Range;
+use std::sync::{Mutex, PoisonError};
+
+enum Format {
+    Json,
+    Ecs,
+    Text(TextFormat),
+    /// The export packet as received
+    Raw,
+}
+
+impl Format {
+    fn from_name(name: &str) -> io::Result<Format> {
+        match name {
+            "json" => Ok(Format::Json),
+            "ecs" => Ok(Format::Ecs),
+            "raw" => Ok(Format::Raw),
+            _ => TextFormat::from_name(name).map(Format::Text).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{}: {}", name, e),
+                )
+            }),
+        }
+    }
+
+    /// Append a record to `buffer`, without line break
+    fn encode(
+        &self,
+        set: &DataFlowset,
+        buffer: &mut Vec<u8>,
+    ) -> io::Result<()> {
+        match self {
+            Format::Json => set.write_json(buffer)?,
+            Format::Ecs => set.write_ecs(buffer)?,
+            Format::Text(format) => {
+                buffer.extend_from_slice(format.format(set).as_bytes())
+            }
+            Format::Raw => unreachable!("raw packets are not encoded"),
+        }
+        Ok(())
+    }
+}
+
+enum Sink {
+    Stdout(io::Stdout),
+    File(BufWriter<File>),
+    Udp(UdpSocket, SocketAddr),
+}
+
+/// Records encoded for an output, each followed by a line
+/// break
+#[derive(Default)]
+pub struct Encoded {
+    bytes: Vec<u8>,
+    /// Records in `bytes`, without their line break
+    records: Vec<Range<usize>>,
+}
+
+impl Encoded {
+    pub fn clear(&mut self) {
+        self.bytes.clear();
+        self.records.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    fn push(&mut self, start: usize) {
+        self.records.push(start..self.bytes.len());
+        self.bytes.push(b'\n');
+    }
+}
+
+/// An output shared by the workers. Records are encoded by
+/// each worker on its own, the sink is only locked to write
+/// them.
+pub struct Output {
+    format: Format,
+    sink: Mutex<Sink>,
+}
+
+impl Output {
+    pub fn open(config: &OutputConfig) -> io::Result<Output> {
+        let (format, sink) = match config {
+            OutputConfig::Stdout { format } => {
+                (Format::from_name(format)?, Sink::Stdout(io::stdout()))
+            }
+            OutputConfig::File { path, format } => {
+                let file =
+                    OpenOptions::new().create(true).append(true).open(path)?;
+                (Format::from_name(format)?, Sink::File(BufWriter::new(file)))
+            }
+            OutputConfig::Udp { address, format } => {
+                let local: SocketAddr = if address.is_ipv4() {
+                    "0.0.0.0:0".parse().unwrap()
+                } else {
+                    "[::]:0".parse().unwrap()
+                };
+                let socket = UdpSocket::bind(local)?;
+                (Format::from_name(format)?, Sink::Udp(socket, *address))
+            }
+        };
+        if let (Format::Raw, Sink::Stdout(_) | Sink::File(_)) = (&format, &sink)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "raw format is only available for udp outputs",
+            ));
+        }
+        Ok(Output {
+            format,
+            sink: Mutex::new(sink),
+        })
+    }
+
+    /// Append the records decoded from `packet` to `encoded`,
+    /// or the packet itself for raw outputs. Records that fail
+    /// to encode are left out.
+    pub fn encode(
+        &self,
+        packet: &[u8],
+        sets: &[DataFlowset],
+        encoded: &mut Encoded,
+    ) -> io::Result<()> {
+        if let Format::Raw = self.format {
+            let start = encoded.bytes.len();
+            encoded.bytes.extend_from_slice(packet);
+            encoded.push(start);
+            return Ok(());
+        }
+        let mut result = Ok(());
+        for set in sets {
+            let start = encoded.bytes.len();
+            match self.format.encode(set, &mut encoded.bytes) {
+                Ok(()) => encoded.push(start),
+                Err(e) => {
+                    encoded.bytes.truncate(start);
+                    result = Err(e);
+                }
+            }
+        }
+        result
+    }
+
+    /// Write the encoded records, one per line or one per
+    /// datagram
+    pub fn write(&self, encoded: &Encoded) -> io::Result<()> {
+        // A worker that panicked while writing is restarted, the
+        // sink stays usable
+        let mut sink = self.sink.lock().unwrap_or_else(PoisonError::into_inner);
+        match &mut *sink {
+            Sink::Stdout(stdout) => {
+                let mut stdout = stdout.lock();
+                stdout.write_all(&encoded.bytes)?;
+                stdout.flush()
+            }
+            Sink::File(file) => {
+                file.write_all(&encoded.bytes)?;
+                file.flush()
+            }
+            Sink::Udp(socket, address) => {
+                for record in &encoded.records {
+                    socket.send_to(&encoded.bytes[record.clone()], *address)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Whether the output needs the packet even when it could
+    /// not be parsed
+    pub fn is_raw(&self) -> bool {
+        matches!(self.format, Format::Raw)
+    }
+}
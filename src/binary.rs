@@ -0,0 +1,86 @@
+use crate::formaters::read_uint;
+use crate::templates::{field_kind, FieldKind};
+use crate::DataFlowset;
+use serde::ser::{self, SerializeMap};
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+/// Serializes a flowset with native value types for binary
+/// formats: integer field IDs as keys, integers for counters
+/// and enumerations, byte strings for addresses and unix
+/// milliseconds for the flow times.
+pub struct Native<'r, 'a>(pub(crate) &'r DataFlowset<'a>);
+
+struct Bytes<'b>(&'b [u8]);
+
+impl<'b> ser::Serialize for Bytes<'b> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: ser::Serializer,
+    {
+        serializer.serialize_bytes(self.0)
+    }
+}
+
+struct SourceIp<'r>(Option<&'r IpAddr>);
+
+impl<'r> ser::Serialize for SourceIp<'r> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: ser::Serializer,
+    {
+        match self.0 {
+            Some(IpAddr::V4(ip)) => serializer.serialize_bytes(&ip.octets()),
+            Some(IpAddr::V6(ip)) => serializer.serialize_bytes(&ip.octets()),
+            None => serializer.serialize_none(),
+        }
+    }
+}
+
+struct Records<'r, 'a>(&'r DataFlowset<'a>);
+
+impl<'r, 'a> ser::Serialize for Records<'r, 'a> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: ser::Serializer,
+    {
+        let records: &HashMap<u16, &[u8]> = &self.0.records;
+        let mut map = serializer.serialize_map(Some(records.len()))?;
+        for (k, v) in records {
+            match field_kind(*k) {
+                FieldKind::Unsigned => match read_uint(v) {
+                    Some(n) => map.serialize_entry(k, &n)?,
+                    None => map.serialize_entry(k, &Bytes(v))?,
+                },
+                FieldKind::Uptime => match read_uint(v) {
+                    Some(n) => map.serialize_entry(
+                        k,
+                        &self.0.uptime_to_millis(n as u32),
+                    )?,
+                    None => map.serialize_entry(k, &Bytes(v))?,
+                },
+                FieldKind::Text => {
+                    let end = v.iter().position(|c| *c == 0).unwrap_or(v.len());
+                    map.serialize_entry(k, &String::from_utf8_lossy(&v[..end]))?
+                }
+                FieldKind::Ipv4 | FieldKind::Ipv6 | FieldKind::Mac => {
+                    map.serialize_entry(k, &Bytes(v))?
+                }
+            }
+        }
+        map.end()
+    }
+}
+
+impl<'r, 'a> ser::Serialize for Native<'r, 'a> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: ser::Serializer,
+    {
+        let mut map = serializer.serialize_map(Some(3))?;
+        map.serialize_entry("source_ip", &SourceIp(self.0.source_ip))?;
+        map.serialize_entry("flowset_id", &self.0.tl_header.flowset_id)?;
+        map.serialize_entry("records", &Records(self.0))?;
+        map.end()
+    }
+}
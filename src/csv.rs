@@ -0,0 +1,151 @@
+use crate::formaters::{FmtReturn, FormatOptions};
+use crate::resolve_hashmap::{decode, is_ipv6};
+use crate::templates::{field_from_name, field_key};
+use crate::DataFlowset;
+use std::io;
+
+/// A column of the CSV output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Column {
+    /// Address of the exporter that sent the flow
+    SourceIp,
+    /// A record field by its ID
+    Field(u16),
+}
+
+impl Column {
+    /// Parse a column name. Accepts `source_ip` and any name
+    /// a field is known by (label, IANA, snake_case, nfdump or
+    /// numeric ID). `sa`, `da` and the other nfdump names of
+    /// both IPv4 and IPv6 fields give the IPv4 field.
+    pub fn from_name(name: &str) -> Option<Column> {
+        if name == "source_ip" {
+            return Some(Column::SourceIp);
+        }
+        field_from_name(name).map(Column::Field)
+    }
+}
+
+/// Writes flowsets as CSV or TSV rows with a fixed set of
+/// columns. Fields missing from a record are left empty.
+#[derive(Debug, Clone)]
+pub struct CsvWriter {
+    columns: Vec<Column>,
+    delimiter: u8,
+    options: FormatOptions,
+}
+
+impl CsvWriter {
+    pub fn new(columns: Vec<Column>) -> Self {
+        CsvWriter {
+            columns,
+            delimiter: b',',
+            options: FormatOptions::default(),
+        }
+    }
+
+    /// Same as `new` but with tab separated columns
+    pub fn tsv(columns: Vec<Column>) -> Self {
+        CsvWriter {
+            delimiter: b'\t',
+            ..CsvWriter::new(columns)
+        }
+    }
+
+    /// Build a writer from column names, see `Column::from_name`
+    pub fn from_names(names: &[&str]) -> Result<Self, &'static str> {
+        let columns = names
+            .iter()
+            .map(|n| Column::from_name(n))
+            .collect::<Option<Vec<_>>>()
+            .ok_or("Unknown column name")?;
+        Ok(CsvWriter::new(columns))
+    }
+
+    pub fn set_delimiter(&mut self, delimiter: u8) {
+        self.delimiter = delimiter
+    }
+
+    /// Set the header naming and enum formatting
+    pub fn set_format_options(&mut self, options: FormatOptions) {
+        self.options = options
+    }
+
+    pub fn write_header<W: io::Write>(&self, mut writer: W) -> io::Result<()> {
+        for (i, c) in self.columns.iter().enumerate() {
+            if i > 0 {
+                writer.write_all(&[self.delimiter])?;
+            }
+            match c {
+                Column::SourceIp => {
+                    self.write_cell(&mut writer, "source_ip")?
+                }
+                Column::Field(f) => self.write_cell(
+                    &mut writer,
+                    &field_key(*f, self.options.keys),
+                )?,
+            }
+        }
+        writer.write_all(b"\n")
+    }
+
+    pub fn write_record<W: io::Write>(
+        &self,
+        set: &DataFlowset,
+        mut writer: W,
+    ) -> io::Result<()> {
+        let ipv6 = is_ipv6(&set.records);
+        for (i, c) in self.columns.iter().enumerate() {
+            if i > 0 {
+                writer.write_all(&[self.delimiter])?;
+            }
+            match c {
+                Column::SourceIp => {
+                    if let Some(ip) = set.source_ip {
+                        write!(writer, "{}", ip)?;
+                    }
+                }
+                Column::Field(f) => {
+                    if let Some(v) = set.records.get(f) {
+                        match decode(*f, v, ipv6).with_style(self.options.enums)
+                        {
+                            FmtReturn::Number(n) => write!(writer, "{}", n)?,
+                            value => self
+                                .write_cell(&mut writer, &value.to_string())?,
+                        }
+                    }
+                }
+            }
+        }
+        writer.write_all(b"\n")
+    }
+
+    pub fn write_records<W: io::Write>(
+        &self,
+        sets: &[DataFlowset],
+        mut writer: W,
+    ) -> io::Result<()> {
+        for set in sets {
+            self.write_record(set, &mut writer)?;
+        }
+        Ok(())
+    }
+
+    /// Write a text cell, quoting it when it contains the
+    /// delimiter, quotes or line breaks
+    fn write_cell<W: io::Write>(
+        &self,
+        writer: &mut W,
+        cell: &str,
+    ) -> io::Result<()> {
+        let needs_quotes = cell.bytes().any(|b| {
+            b == self.delimiter || b == b'"' || b == b'\n' || b == b'\r'
+        });
+        if !needs_quotes {
+            return writer.write_all(cell.as_bytes());
+        }
+        writer.write_all(b"\"")?;
+        writer.write_all(cell.replace('"', "\"\"").as_bytes())?;
+        writer.write_all(b"\"")
+    }
+}
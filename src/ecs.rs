@@ -0,0 +1,165 @@
+use crate::formaters::{fmt_rfc3339_millis, protocol_name, read_uint};
+use crate::resolve_hashmap::{decode, is_ipv6};
+use crate::templates::iana_snake_name;
+use crate::DataFlowset;
+use serde_json::{json, Map, Value};
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+/// Where a field goes in an ECS document
+enum Target {
+    Ip(&'static str),
+    Number(&'static str),
+    Text(&'static str),
+    Mac(&'static str),
+    /// Fields handled separately
+    Skip,
+}
+
+fn ecs_target(field: u16) -> Option<Target> {
+    let target = match field {
+        8 | 27 => Target::Ip("source.ip"),
+        12 | 28 => Target::Ip("destination.ip"),
+        7 => Target::Number("source.port"),
+        11 => Target::Number("destination.port"),
+        16 => Target::Number("source.as.number"),
+        17 => Target::Number("destination.as.number"),
+        1 => Target::Number("network.bytes"),
+        2 => Target::Number("network.packets"),
+        10 => Target::Text("observer.ingress.interface.id"),
+        14 => Target::Text("observer.egress.interface.id"),
+        58 => Target::Text("network.vlan.id"),
+        56 => Target::Mac("source.mac"),
+        80 => Target::Mac("destination.mac"),
+        4 | 21 | 22 | 60 | 61 => Target::Skip,
+        _ => return None,
+    };
+    Some(target)
+}
+
+/// Set `value` at a dotted `path`, creating the intermediate
+/// objects
+fn insert(doc: &mut Map<String, Value>, path: &str, value: Value) {
+    let mut parts = path.split('.').peekable();
+    let mut node = doc;
+    while let Some(part) = parts.next() {
+        if parts.peek().is_none() {
+            node.insert(part.to_owned(), value);
+            return;
+        }
+        let child = node
+            .entry(part.to_owned())
+            .or_insert_with(|| Value::Object(Map::new()));
+        if !child.is_object() {
+            *child = Value::Object(Map::new());
+        }
+        node = child.as_object_mut().unwrap();
+    }
+}
+
+fn ip_value(v: &[u8]) -> Option<Value> {
+    match v.len() {
+        4 => {
+            let mut octets = [0; 4];
+            octets.copy_from_slice(v);
+            Some(Ipv4Addr::from(octets).to_string().into())
+        }
+        16 => {
+            let mut octets = [0; 16];
+            octets.copy_from_slice(v);
+            Some(Ipv6Addr::from(octets).to_string().into())
+        }
+        _ => None,
+    }
+}
+
+fn mac_value(v: &[u8]) -> Value {
+    let octets: Vec<String> = v.iter().map(|b| format!("{:02X}", b)).collect();
+    octets.join("-").into()
+}
+
+/// Build an Elastic Common Schema document out of a flowset.
+/// Fields without an ECS equivalent go under `netflow.*`
+/// with their snake_case IANA name.
+pub fn to_ecs(set: &DataFlowset) -> Value {
+    let mut doc = Map::new();
+    let ipv6 = is_ipv6(&set.records);
+
+    let start = set.flow_start_millis();
+    let end = set.flow_end_millis();
+    let timestamp = end.unwrap_or(u64::from(set.export_time()) * 1000);
+    insert(&mut doc, "@timestamp", fmt_rfc3339_millis(timestamp).into());
+    insert(&mut doc, "event.kind", "event".into());
+    insert(&mut doc, "event.category", json!(["network"]));
+    insert(&mut doc, "event.type", json!(["connection"]));
+    if let Some(start) = start {
+        insert(&mut doc, "event.start", fmt_rfc3339_millis(start).into());
+    }
+    if let Some(end) = end {
+        insert(&mut doc, "event.end", fmt_rfc3339_millis(end).into());
+    }
+    if let (Some(start), Some(end)) = (start, end) {
+        let duration = end.saturating_sub(start) * 1_000_000;
+        insert(&mut doc, "event.duration", duration.into());
+    }
+
+    if let Some(ip) = set.source_ip {
+        insert(&mut doc, "observer.ip", ip.to_string().into());
+        insert(&mut doc, "netflow.exporter.address", ip.to_string().into());
+    }
+    insert(&mut doc, "netflow.exporter.version", 9.into());
+    insert(
+        &mut doc,
+        "netflow.exporter.source_id",
+        set.source_id().into(),
+    );
+    insert(
+        &mut doc,
+        "netflow.exporter.uptime_millis",
+        set.sys_uptime().into(),
+    );
+    let network_type = if ipv6 { "ipv6" } else { "ipv4" };
+    insert(&mut doc, "network.type", network_type.into());
+    if let Some(protocol) = set.records.get(&4).and_then(|v| read_uint(v)) {
+        insert(&mut doc, "network.iana_number", protocol.to_string().into());
+        if let Some(name) = protocol_name(protocol) {
+            insert(&mut doc, "network.transport", name.to_lowercase().into());
+        }
+    }
+    match set.records.get(&61).and_then(|v| read_uint(v)) {
+        Some(0) => insert(&mut doc, "network.direction", "ingress".into()),
+        Some(1) => insert(&mut doc, "network.direction", "egress".into()),
+        _ => {}
+    }
+
+    for (field, v) in &set.records {
+        match ecs_target(*field) {
+            Some(Target::Ip(path)) => {
+                if let Some(ip) = ip_value(v) {
+                    insert(&mut doc, path, ip);
+                }
+            }
+            Some(Target::Number(path)) => {
+                if let Some(n) = read_uint(v) {
+                    insert(&mut doc, path, n.into());
+                }
+            }
+            Some(Target::Text(path)) => {
+                if let Some(n) = read_uint(v) {
+                    insert(&mut doc, path, n.to_string().into());
+                }
+            }
+            Some(Target::Mac(path)) => insert(&mut doc, path, mac_value(v)),
+            Some(Target::Skip) => {}
+            None => {
+                let name = iana_snake_name(*field)
+                    .unwrap_or_else(|| format!("field_{}", field));
+                let value =
+                    decode(*field, v, ipv6).with_style(set.options.enums);
+                let value = serde_json::to_value(value).unwrap_or(Value::Null);
+                insert(&mut doc, &format!("netflow.{}", name), value);
+            }
+        }
+    }
+
+    Value::Object(doc)
+}
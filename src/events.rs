@@ -0,0 +1,112 @@
+use crate::template_store::TemplateKey;
+use std::fmt;
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// Events are kept until `Parser::drain_events` takes them,
+/// the oldest are dropped beyond this
+pub(crate) const MAX_EVENTS: usize = 1024;
+
+/// Something noteworthy that happened to the templates of a
+/// `Parser`, see `Parser::drain_events`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TemplateEvent {
+    /// A template received from the exporter differs from the
+    /// static definition for the same key. `overridden` tells
+    /// whether the received template is used from now on.
+    StaticMismatch { key: TemplateKey, overridden: bool },
+    /// A template was received with a different layout than
+    /// the one known for its key, which it replaces
+    Redefined { key: TemplateKey },
+    /// A template with no fields was received, the template of
+    /// its key was removed
+    Withdrawn { key: TemplateKey },
+    /// The uptime or sequence number of the exporter went back,
+    /// its `flushed` templates were removed
+    ExporterRestarted {
+        exporter: IpAddr,
+        source_id: u32,
+        flushed: usize,
+    },
+    /// Templates not refreshed within the template timeout were
+    /// removed
+    Expired { count: usize },
+    /// A pinned template was redefined or withdrawn, which was
+    /// ignored. See `Parser::pin_exporters`.
+    PinnedRedefinition { key: TemplateKey },
+    /// A template was redefined `count` times within `window`,
+    /// more than allowed by `Parser::set_redefinition_limit`
+    RedefinitionRate {
+        key: TemplateKey,
+        count: u32,
+        window: Duration,
+    },
+}
+
+impl TemplateEvent {
+    /// Whether the event may be an attack or a misconfigured
+    /// exporter rather than normal operation
+    pub fn is_alert(&self) -> bool {
+        matches!(
+            self,
+            TemplateEvent::PinnedRedefinition { .. }
+                | TemplateEvent::RedefinitionRate { .. }
+        )
+    }
+}
+
+impl fmt::Display for TemplateEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TemplateEvent::StaticMismatch { key, overridden } => write!(
+                f,
+                "{}: template {} (source ID {}) differs from its static \
+                 definition, {}",
+                key.exporter,
+                key.template_id,
+                key.source_id,
+                if *overridden {
+                    "using the received one"
+                } else {
+                    "keeping the static one"
+                }
+            ),
+            TemplateEvent::Redefined { key } => write!(
+                f,
+                "{}: template {} (source ID {}) redefined",
+                key.exporter, key.template_id, key.source_id
+            ),
+            TemplateEvent::Withdrawn { key } => write!(
+                f,
+                "{}: template {} (source ID {}) withdrawn",
+                key.exporter, key.template_id, key.source_id
+            ),
+            TemplateEvent::ExporterRestarted {
+                exporter,
+                source_id,
+                flushed,
+            } => write!(
+                f,
+                "{}: exporter restarted (source ID {}), {} templates flushed",
+                exporter, source_id, flushed
+            ),
+            TemplateEvent::Expired { count } => {
+                write!(f, "{} templates expired", count)
+            }
+            TemplateEvent::PinnedRedefinition { key } => write!(
+                f,
+                "{}: pinned template {} (source ID {}) redefined, ignored",
+                key.exporter, key.template_id, key.source_id
+            ),
+            TemplateEvent::RedefinitionRate { key, count, window } => write!(
+                f,
+                "{}: template {} (source ID {}) redefined {} times in {}s",
+                key.exporter,
+                key.template_id,
+                key.source_id,
+                count,
+                window.as_secs()
+            ),
+        }
+    }
+}
@@ -0,0 +1,203 @@
+use crate::template_store::TemplateKey;
+use crate::NetflowHeader;
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+/// How far back, in milliseconds, `sys_uptime` can go between
+/// two packets before the exporter is considered restarted.
+/// Packets are sometimes reordered on the way.
+const UPTIME_TOLERANCE: u32 = 5_000;
+
+/// How far back the sequence number can go between two packets
+/// before it is considered reset
+const SEQUENCE_TOLERANCE: u32 = 1_000;
+
+/// Uptime and sequence number of the last packet of an exporter
+/// and source ID, to tell when it restarted
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ExporterClock {
+    sys_uptime: u32,
+    sequence: u32,
+}
+
+/// Whether `new` is behind `old` by more than `tolerance`, as
+/// opposed to ahead of it, possibly after wrapping around
+fn went_back(old: u32, new: u32, tolerance: u32) -> bool {
+    let back = old.wrapping_sub(new);
+    back > tolerance && back < 1 << 31
+}
+
+impl ExporterClock {
+    pub(crate) fn new(header: &NetflowHeader) -> Self {
+        ExporterClock {
+            sys_uptime: header.sys_uptime,
+            sequence: header.sequence,
+        }
+    }
+
+    /// Record the packet with `header`, returning whether the
+    /// exporter restarted since the previous one
+    pub(crate) fn update(&mut self, header: &NetflowHeader) -> bool {
+        let restarted =
+            went_back(self.sys_uptime, header.sys_uptime, UPTIME_TOLERANCE)
+                || went_back(
+                    self.sequence,
+                    header.sequence,
+                    SEQUENCE_TOLERANCE,
+                );
+        if restarted {
+            *self = ExporterClock::new(header);
+            return true;
+        }
+        // Reordered packets must not move the clock back
+        if !went_back(self.sys_uptime, header.sys_uptime, 0) {
+            self.sys_uptime = header.sys_uptime;
+        }
+        if !went_back(self.sequence, header.sequence, 0) {
+            self.sequence = header.sequence;
+        }
+        false
+    }
+}
+
+/// Clocks are kept for this many exporters unless the store
+/// sets `TemplateLimits::max_exporters`, and for this many
+/// source IDs per exporter
+const MAX_EXPORTERS: usize = 4096;
+const MAX_SOURCE_IDS: usize = 256;
+
+/// The clocks of the exporters and source IDs that have
+/// templates, restarts don't matter to the others. Clocks are
+/// added on the first packet and dropped when the templates
+/// are gone or to make room for others.
+#[derive(Debug, Clone)]
+pub(crate) struct ExporterClocks {
+    clocks: HashMap<IpAddr, HashMap<u32, ExporterClock>>,
+    /// Templates may have been removed since the last prune
+    stale: bool,
+    pruned: Option<Instant>,
+}
+
+impl ExporterClocks {
+    pub(crate) fn new() -> Self {
+        ExporterClocks {
+            clocks: HashMap::new(),
+            stale: false,
+            pruned: None,
+        }
+    }
+
+    pub(crate) fn get_mut(
+        &mut self,
+        exporter: &IpAddr,
+        source_id: u32,
+    ) -> Option<&mut ExporterClock> {
+        self.clocks.get_mut(exporter)?.get_mut(&source_id)
+    }
+
+    /// Whether there is no room for a new clock of `exporter`
+    pub(crate) fn is_full(
+        &self,
+        exporter: &IpAddr,
+        max_exporters: Option<usize>,
+    ) -> bool {
+        match self.clocks.get(exporter) {
+            Some(clocks) => clocks.len() >= MAX_SOURCE_IDS,
+            None => self.clocks.len() >= max_exporters.unwrap_or(MAX_EXPORTERS),
+        }
+    }
+
+    /// Add a clock unless `is_full`
+    pub(crate) fn insert(
+        &mut self,
+        exporter: IpAddr,
+        header: &NetflowHeader,
+        max_exporters: Option<usize>,
+    ) {
+        if !self.is_full(&exporter, max_exporters) {
+            self.clocks
+                .entry(exporter)
+                .or_default()
+                .insert(header.source_id, ExporterClock::new(header));
+        }
+    }
+
+    /// Note that templates were evicted or expired
+    pub(crate) fn mark_stale(&mut self) {
+        self.stale = true
+    }
+
+    /// Whether clocks should be pruned, once a second at most
+    pub(crate) fn prune_due(&self, now: Instant) -> bool {
+        match self.pruned {
+            _ if !self.stale => false,
+            Some(pruned) => {
+                now.duration_since(pruned) >= Duration::from_secs(1)
+            }
+            None => true,
+        }
+    }
+
+    /// Keep the clocks of the exporters and source IDs in `live`
+    pub(crate) fn retain(
+        &mut self,
+        live: &HashSet<(IpAddr, u32)>,
+        now: Instant,
+    ) {
+        self.clocks.retain(|exporter, clocks| {
+            clocks
+                .retain(|source_id, _| live.contains(&(*exporter, *source_id)));
+            !clocks.is_empty()
+        });
+        self.stale = false;
+        self.pruned = Some(now);
+    }
+}
+
+/// Counts the redefinitions of each template to report those
+/// redefined more than `max` times within `window`
+#[derive(Debug, Clone)]
+pub(crate) struct RedefinitionRate {
+    max: u32,
+    window: Duration,
+    /// Start of the current window and redefinitions within it
+    counts: HashMap<TemplateKey, (Instant, u32)>,
+}
+
+/// Beyond this many templates counted, those whose window is
+/// over are forgotten
+const MAX_COUNTED: usize = 4096;
+
+impl RedefinitionRate {
+    pub(crate) fn new(max: u32, window: Duration) -> Self {
+        RedefinitionRate {
+            max,
+            window,
+            counts: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// Count a redefinition of `key`, returning the number of
+    /// redefinitions within the window when it just went over
+    /// the maximum
+    pub(crate) fn redefined(&mut self, key: TemplateKey) -> Option<u32> {
+        let now = Instant::now();
+        let window = self.window;
+        if self.counts.len() >= MAX_COUNTED {
+            self.counts
+                .retain(|_, (start, _)| now.duration_since(*start) < window);
+        }
+        let (start, count) = self.counts.entry(key).or_insert((now, 0));
+        if now.duration_since(*start) >= window {
+            *start = now;
+            *count = 0;
+        }
+        *count += 1;
+        (*count == self.max + 1).then_some(*count)
+    }
+}
@@ -1,4 +1,5 @@
-use byteorder::{BigEndian, ByteOrder, ReadBytesExt};
+use byteorder::{BigEndian, ByteOrder};
+use serde::ser::SerializeMap;
 use serde::Serialize;
 use std::net::{Ipv4Addr, Ipv6Addr};
 
@@ -17,6 +18,243 @@ static TCP_FLAGS: [(u8, &str); 8] = [
 pub enum FmtReturn {
     Number(u64),
     Text(String),
+    Icmp(IcmpTypeCode),
+    Enum(EnumValue),
+}
+
+/// How enumerated fields (protocol, direction...) are
+/// serialized. The ICMP type field is split into its type and
+/// code by name, e.g. `"Destination Unreachable/Port
+/// Unreachable"`, and into `{"type":3,"code":3,...}` with both.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EnumStyle {
+    /// The numeric code only, e.g. `17`
+    #[default]
+    Code,
+    /// The symbolic name, e.g. `"UDP"`. Codes without a known
+    /// name are kept numeric.
+    Name,
+    /// Both, e.g. `{"code":17,"name":"UDP"}`
+    Both,
+}
+
+/// How record fields are named when used as keys
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum KeyStyle {
+    /// Human readable labels, e.g. `"IPv4 Src Addr"`
+    #[default]
+    Label,
+    /// IANA IPFIX information element names, e.g.
+    /// `"sourceIPv4Address"`
+    Iana,
+    /// Labels in snake_case, e.g. `"ipv4_src_addr"`
+    SnakeCase,
+    /// nfdump short names, e.g. `"sa"`
+    Nfdump,
+    /// Numeric field IDs, e.g. `"8"`
+    Id,
+}
+
+/// Options controlling how a `DataFlowset` is serialized
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FormatOptions {
+    pub enums: EnumStyle,
+    pub keys: KeyStyle,
+}
+
+/// Numeric code of an enumerated field along with its
+/// symbolic name, if known
+#[derive(Debug, Clone, Copy)]
+pub struct EnumValue {
+    pub code: u64,
+    pub name: Option<&'static str>,
+}
+
+impl Serialize for EnumValue {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::ser::Serializer,
+    {
+        let mut map = serializer.serialize_map(Some(2))?;
+        map.serialize_entry("code", &self.code)?;
+        map.serialize_entry("name", &self.name)?;
+        map.end()
+    }
+}
+
+impl std::fmt::Display for EnumValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.name {
+            Some(name) => write!(f, "{}", name),
+            None => write!(f, "{}", self.code),
+        }
+    }
+}
+
+/// ICMP type and code as carried in the `ICMP type` field
+/// (type * 256 + code)
+#[derive(Debug, Clone, Copy)]
+pub struct IcmpTypeCode {
+    pub icmp_type: u8,
+    pub code: u8,
+    pub ipv6: bool,
+}
+
+impl IcmpTypeCode {
+    /// The value of the field, type * 256 + code
+    pub fn value(&self) -> u64 {
+        u64::from(self.icmp_type) << 8 | u64::from(self.code)
+    }
+
+    pub fn type_name(&self) -> Option<&'static str> {
+        if self.ipv6 {
+            icmpv6_type_name(self.icmp_type)
+        } else {
+            icmpv4_type_name(self.icmp_type)
+        }
+    }
+
+    pub fn code_name(&self) -> Option<&'static str> {
+        if self.ipv6 {
+            icmpv6_code_name(self.icmp_type, self.code)
+        } else {
+            icmpv4_code_name(self.icmp_type, self.code)
+        }
+    }
+}
+
+impl Serialize for IcmpTypeCode {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::ser::Serializer,
+    {
+        let type_name = self.type_name();
+        let code_name = self.code_name();
+        let len =
+            2 + type_name.is_some() as usize + code_name.is_some() as usize;
+
+        let mut map = serializer.serialize_map(Some(len))?;
+        map.serialize_entry("type", &self.icmp_type)?;
+        map.serialize_entry("code", &self.code)?;
+        if let Some(name) = type_name {
+            map.serialize_entry("type_name", name)?;
+        }
+        if let Some(name) = code_name {
+            map.serialize_entry("code_name", name)?;
+        }
+        map.end()
+    }
+}
+
+impl std::fmt::Display for IcmpTypeCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.type_name() {
+            Some(t) => write!(f, "{}", t)?,
+            None => write!(f, "{}", self.icmp_type)?,
+        }
+        match self.code_name() {
+            Some(c) => write!(f, "/{}", c),
+            None => write!(f, "/{}", self.code),
+        }
+    }
+}
+
+fn icmpv4_type_name(icmp_type: u8) -> Option<&'static str> {
+    let name = match icmp_type {
+        0 => "Echo Reply",
+        3 => "Destination Unreachable",
+        4 => "Source Quench",
+        5 => "Redirect",
+        8 => "Echo Request",
+        9 => "Router Advertisement",
+        10 => "Router Solicitation",
+        11 => "Time Exceeded",
+        12 => "Parameter Problem",
+        13 => "Timestamp",
+        14 => "Timestamp Reply",
+        15 => "Information Request",
+        16 => "Information Reply",
+        17 => "Address Mask Request",
+        18 => "Address Mask Reply",
+        30 => "Traceroute",
+        42 => "Extended Echo Request",
+        43 => "Extended Echo Reply",
+        _ => return None,
+    };
+    Some(name)
+}
+
+fn icmpv4_code_name(icmp_type: u8, code: u8) -> Option<&'static str> {
+    let name = match (icmp_type, code) {
+        (3, 0) => "Net Unreachable",
+        (3, 1) => "Host Unreachable",
+        (3, 2) => "Protocol Unreachable",
+        (3, 3) => "Port Unreachable",
+        (3, 4) => "Fragmentation Needed",
+        (3, 5) => "Source Route Failed",
+        (3, 6) => "Destination Network Unknown",
+        (3, 7) => "Destination Host Unknown",
+        (3, 8) => "Source Host Isolated",
+        (3, 9) => "Network Administratively Prohibited",
+        (3, 10) => "Host Administratively Prohibited",
+        (3, 11) => "Network Unreachable for TOS",
+        (3, 12) => "Host Unreachable for TOS",
+        (3, 13) => "Communication Administratively Prohibited",
+        (3, 14) => "Host Precedence Violation",
+        (3, 15) => "Precedence Cutoff in Effect",
+        (5, 0) => "Redirect for Network",
+        (5, 1) => "Redirect for Host",
+        (5, 2) => "Redirect for TOS and Network",
+        (5, 3) => "Redirect for TOS and Host",
+        (11, 0) => "TTL Exceeded in Transit",
+        (11, 1) => "Fragment Reassembly Time Exceeded",
+        (12, 0) => "Pointer Indicates the Error",
+        (12, 1) => "Missing a Required Option",
+        (12, 2) => "Bad Length",
+        _ => return None,
+    };
+    Some(name)
+}
+
+fn icmpv6_type_name(icmp_type: u8) -> Option<&'static str> {
+    let name = match icmp_type {
+        1 => "Destination Unreachable",
+        2 => "Packet Too Big",
+        3 => "Time Exceeded",
+        4 => "Parameter Problem",
+        128 => "Echo Request",
+        129 => "Echo Reply",
+        130 => "Multicast Listener Query",
+        131 => "Multicast Listener Report",
+        132 => "Multicast Listener Done",
+        133 => "Router Solicitation",
+        134 => "Router Advertisement",
+        135 => "Neighbor Solicitation",
+        136 => "Neighbor Advertisement",
+        137 => "Redirect",
+        143 => "Multicast Listener Report v2",
+        _ => return None,
+    };
+    Some(name)
+}
+
+fn icmpv6_code_name(icmp_type: u8, code: u8) -> Option<&'static str> {
+    let name = match (icmp_type, code) {
+        (1, 0) => "No Route to Destination",
+        (1, 1) => "Communication Administratively Prohibited",
+        (1, 2) => "Beyond Scope of Source Address",
+        (1, 3) => "Address Unreachable",
+        (1, 4) => "Port Unreachable",
+        (1, 5) => "Source Address Failed Ingress/Egress Policy",
+        (1, 6) => "Reject Route to Destination",
+        (3, 0) => "Hop Limit Exceeded in Transit",
+        (3, 1) => "Fragment Reassembly Time Exceeded",
+        (4, 0) => "Erroneous Header Field",
+        (4, 1) => "Unrecognized Next Header Type",
+        (4, 2) => "Unrecognized IPv6 Option",
+        _ => return None,
+    };
+    Some(name)
 }
 
 impl Serialize for FmtReturn {
@@ -27,6 +265,8 @@ impl Serialize for FmtReturn {
         match self {
             FmtReturn::Number(n) => serializer.serialize_u64(*n),
             FmtReturn::Text(t) => serializer.serialize_str(t),
+            FmtReturn::Icmp(i) => i.serialize(serializer),
+            FmtReturn::Enum(e) => e.serialize(serializer),
         }
     }
 }
@@ -36,6 +276,28 @@ impl std::fmt::Display for FmtReturn {
         match self {
             FmtReturn::Number(n) => write!(f, "{}", n),
             FmtReturn::Text(t) => write!(f, "{}", t),
+            FmtReturn::Icmp(i) => write!(f, "{}", i),
+            FmtReturn::Enum(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl FmtReturn {
+    /// Collapse enumerated values according to the requested style
+    pub fn with_style(self, style: EnumStyle) -> FmtReturn {
+        match (self, style) {
+            (FmtReturn::Enum(e), EnumStyle::Code) => FmtReturn::Number(e.code),
+            (FmtReturn::Enum(e), EnumStyle::Name) => match e.name {
+                Some(name) => FmtReturn::Text(name.to_owned()),
+                None => FmtReturn::Number(e.code),
+            },
+            (FmtReturn::Icmp(i), EnumStyle::Code) => {
+                FmtReturn::Number(i.value())
+            }
+            (FmtReturn::Icmp(i), EnumStyle::Name) => {
+                FmtReturn::Text(i.to_string())
+            }
+            (r, _) => r,
         }
     }
 }
@@ -45,37 +307,19 @@ pub fn fmt_ipv4(b: &[u8]) -> FmtReturn {
 }
 
 pub fn fmt_ipv6(b: &[u8]) -> FmtReturn {
-    FmtReturn::Text(Ipv6Addr::from(BigEndian::read_u128(&b)).to_string())
+    FmtReturn::Text(Ipv6Addr::from(BigEndian::read_u128(b)).to_string())
 }
 
-pub fn fmt_int(mut b: &[u8]) -> FmtReturn {
-    let val = match b.len() {
-        1 => b[0] as u64,
-        2 => {
-            if let Ok(num) = b.read_u16::<BigEndian>() {
-                num as u64
-            } else {
-                0 as u64
-            }
-        }
-        4 => {
-            if let Ok(num) = b.read_u32::<BigEndian>() {
-                num as u64
-            } else {
-                0 as u64
-            }
-        }
-        8 => {
-            if let Ok(num) = b.read_u64::<BigEndian>() {
-                num
-            } else {
-                0 as u64
-            }
-        }
-        _ => 0,
-    };
+/// Read a big endian unsigned integer of up to 8 bytes
+pub fn read_uint(b: &[u8]) -> Option<u64> {
+    match b.len() {
+        1..=8 => Some(BigEndian::read_uint(b, b.len())),
+        _ => None,
+    }
+}
 
-    FmtReturn::Number(val)
+pub fn fmt_int(b: &[u8]) -> FmtReturn {
+    FmtReturn::Number(read_uint(b).unwrap_or(0))
 }
 
 pub fn fmt_tcp_flags(b: &[u8]) -> FmtReturn {
@@ -96,3 +340,171 @@ pub fn fmt_tcp_flags(b: &[u8]) -> FmtReturn {
 
     FmtReturn::Text(res)
 }
+
+/// The field is 2 bytes but some exporters send it in 4.
+/// Values too large for a type and code stay numeric.
+fn icmp_type_code(b: &[u8], ipv6: bool) -> FmtReturn {
+    let value = read_uint(b).unwrap_or(0);
+    if value > 0xffff {
+        return FmtReturn::Number(value);
+    }
+    FmtReturn::Icmp(IcmpTypeCode {
+        icmp_type: (value >> 8) as u8,
+        code: value as u8,
+        ipv6,
+    })
+}
+
+pub fn fmt_icmp(b: &[u8]) -> FmtReturn {
+    icmp_type_code(b, false)
+}
+
+pub fn fmt_icmpv6(b: &[u8]) -> FmtReturn {
+    icmp_type_code(b, true)
+}
+
+pub fn protocol_name(protocol: u64) -> Option<&'static str> {
+    let name = match protocol {
+        0 => "HOPOPT",
+        1 => "ICMP",
+        2 => "IGMP",
+        4 => "IPv4",
+        6 => "TCP",
+        8 => "EGP",
+        9 => "IGP",
+        17 => "UDP",
+        27 => "RDP",
+        41 => "IPv6",
+        43 => "IPv6-Route",
+        44 => "IPv6-Frag",
+        46 => "RSVP",
+        47 => "GRE",
+        50 => "ESP",
+        51 => "AH",
+        58 => "IPv6-ICMP",
+        59 => "IPv6-NoNxt",
+        60 => "IPv6-Opts",
+        88 => "EIGRP",
+        89 => "OSPFIGP",
+        94 => "IPIP",
+        103 => "PIM",
+        112 => "VRRP",
+        115 => "L2TP",
+        132 => "SCTP",
+        136 => "UDPLite",
+        137 => "MPLS-in-IP",
+        _ => return None,
+    };
+    Some(name)
+}
+
+/// Forwarding status is a 2-bit status followed by a 6-bit
+/// reason code (RFC 7270)
+fn forwarding_status_name(status: u64) -> Option<&'static str> {
+    let name = match status {
+        0 => "Unknown",
+        64 => "Forwarded: Unknown",
+        65 => "Forwarded: Fragmented",
+        66 => "Forwarded: Not Fragmented",
+        128 => "Dropped: Unknown",
+        129 => "Dropped: ACL Deny",
+        130 => "Dropped: ACL Drop",
+        131 => "Dropped: Unroutable",
+        132 => "Dropped: Adjacency",
+        133 => "Dropped: Fragmentation and DF set",
+        134 => "Dropped: Bad header checksum",
+        135 => "Dropped: Bad total length",
+        136 => "Dropped: Bad header length",
+        137 => "Dropped: Bad TTL",
+        138 => "Dropped: Policer",
+        139 => "Dropped: WRED",
+        140 => "Dropped: RPF",
+        141 => "Dropped: For us",
+        142 => "Dropped: Bad output interface",
+        143 => "Dropped: Hardware",
+        192 => "Consumed: Unknown",
+        193 => "Consumed: Punt Adjacency",
+        194 => "Consumed: Incomplete Adjacency",
+        195 => "Consumed: For us",
+        _ => match status >> 6 {
+            1 => "Forwarded",
+            2 => "Dropped",
+            3 => "Consumed",
+            _ => return None,
+        },
+    };
+    Some(name)
+}
+
+fn direction_name(direction: u64) -> Option<&'static str> {
+    match direction {
+        0 => Some("Ingress"),
+        1 => Some("Egress"),
+        _ => None,
+    }
+}
+
+fn sampling_algorithm_name(algorithm: u64) -> Option<&'static str> {
+    match algorithm {
+        1 => Some("Deterministic"),
+        2 => Some("Random"),
+        _ => None,
+    }
+}
+
+fn fmt_enum(b: &[u8], name: fn(u64) -> Option<&'static str>) -> FmtReturn {
+    let code = match fmt_int(b) {
+        FmtReturn::Number(n) => n,
+        _ => 0,
+    };
+    FmtReturn::Enum(EnumValue {
+        code,
+        name: name(code),
+    })
+}
+
+pub fn fmt_protocol(b: &[u8]) -> FmtReturn {
+    fmt_enum(b, protocol_name)
+}
+
+pub fn fmt_forwarding_status(b: &[u8]) -> FmtReturn {
+    fmt_enum(b, forwarding_status_name)
+}
+
+pub fn fmt_direction(b: &[u8]) -> FmtReturn {
+    fmt_enum(b, direction_name)
+}
+
+pub fn fmt_sampling_algorithm(b: &[u8]) -> FmtReturn {
+    fmt_enum(b, sampling_algorithm_name)
+}
+
+/// Format unix milliseconds as an RFC 3339 UTC timestamp,
+/// e.g. `2017-11-30T22:24:09.760Z`
+pub fn fmt_rfc3339_millis(ms: u64) -> String {
+    let secs = ms / 1000;
+    let (days, rem) = (secs / 86400, secs % 86400);
+
+    // Civil date from days since the epoch, after
+    // http://howardhinnant.github.io/date_algorithms.html
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60,
+        ms % 1000
+    )
+}
@@ -0,0 +1,236 @@
+use crate::formaters::read_uint;
+use crate::resolve_hashmap::is_ipv6;
+use crate::DataFlowset;
+use prost::Message;
+use std::io;
+use std::net::IpAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration,
+)]
+#[repr(i32)]
+pub enum FlowType {
+    FlowUnknown = 0,
+    Sflow5 = 1,
+    NetflowV5 = 2,
+    NetflowV9 = 3,
+    Ipfix = 4,
+}
+
+/// goflow2 `FlowMessage` (`pb/flow.proto`). Only the fields a
+/// NetFlow v9 record can fill are declared, with the tags of
+/// the goflow2 schema.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct FlowMessage {
+    #[prost(enumeration = "FlowType", tag = "1")]
+    pub r#type: i32,
+    #[prost(uint64, tag = "110")]
+    pub time_received_ns: u64,
+    #[prost(uint32, tag = "4")]
+    pub sequence_num: u32,
+    #[prost(uint64, tag = "3")]
+    pub sampling_rate: u64,
+    #[prost(bytes = "vec", tag = "11")]
+    pub sampler_address: Vec<u8>,
+    #[prost(uint64, tag = "111")]
+    pub time_flow_start_ns: u64,
+    #[prost(uint64, tag = "112")]
+    pub time_flow_end_ns: u64,
+    #[prost(uint64, tag = "9")]
+    pub bytes: u64,
+    #[prost(uint64, tag = "10")]
+    pub packets: u64,
+    #[prost(bytes = "vec", tag = "6")]
+    pub src_addr: Vec<u8>,
+    #[prost(bytes = "vec", tag = "7")]
+    pub dst_addr: Vec<u8>,
+    #[prost(uint32, tag = "30")]
+    pub etype: u32,
+    #[prost(uint32, tag = "20")]
+    pub proto: u32,
+    #[prost(uint32, tag = "21")]
+    pub src_port: u32,
+    #[prost(uint32, tag = "22")]
+    pub dst_port: u32,
+    #[prost(uint32, tag = "18")]
+    pub in_if: u32,
+    #[prost(uint32, tag = "19")]
+    pub out_if: u32,
+    #[prost(uint64, tag = "27")]
+    pub src_mac: u64,
+    #[prost(uint64, tag = "28")]
+    pub dst_mac: u64,
+    #[prost(uint32, tag = "33")]
+    pub src_vlan: u32,
+    #[prost(uint32, tag = "34")]
+    pub dst_vlan: u32,
+    #[prost(uint32, tag = "23")]
+    pub ip_tos: u32,
+    #[prost(uint32, tag = "24")]
+    pub forwarding_status: u32,
+    #[prost(uint32, tag = "25")]
+    pub ip_ttl: u32,
+    #[prost(uint32, tag = "26")]
+    pub tcp_flags: u32,
+    #[prost(uint32, tag = "31")]
+    pub icmp_type: u32,
+    #[prost(uint32, tag = "32")]
+    pub icmp_code: u32,
+    #[prost(uint32, tag = "37")]
+    pub ipv6_flow_label: u32,
+    #[prost(uint32, tag = "35")]
+    pub fragment_id: u32,
+    #[prost(uint32, tag = "14")]
+    pub src_as: u32,
+    #[prost(uint32, tag = "15")]
+    pub dst_as: u32,
+    #[prost(bytes = "vec", tag = "12")]
+    pub next_hop: Vec<u8>,
+    #[prost(uint32, tag = "16")]
+    pub src_net: u32,
+    #[prost(uint32, tag = "17")]
+    pub dst_net: u32,
+    #[prost(bytes = "vec", tag = "100")]
+    pub bgp_next_hop: Vec<u8>,
+    #[prost(uint32, repeated, tag = "81")]
+    pub mpls_label: Vec<u32>,
+    #[prost(uint32, tag = "70")]
+    pub observation_domain_id: u32,
+}
+
+impl FlowMessage {
+    /// Longest message `read_length_delimited` accepts. Flow
+    /// messages are a few hundred bytes, a larger length means
+    /// a corrupt or hostile stream.
+    pub const MAX_LEN: u64 = 64 * 1024;
+
+    /// Map a flowset onto a `FlowMessage`. `time_received` is
+    /// when the export packet was received by the collector.
+    pub fn from_flowset(set: &DataFlowset, time_received: SystemTime) -> Self {
+        let uint = |field: u16| -> u64 {
+            set.records
+                .get(&field)
+                .and_then(|v| read_uint(v))
+                .unwrap_or(0)
+        };
+        let bytes = |v4: u16, v6: u16| -> Vec<u8> {
+            set.records
+                .get(&v4)
+                .or_else(|| set.records.get(&v6))
+                .map(|v| v.to_vec())
+                .unwrap_or_default()
+        };
+        let ms_to_ns = |ms: Option<u64>| ms.unwrap_or(0) * 1_000_000;
+        let ipv6 = is_ipv6(&set.records);
+
+        let sampling_rate = match uint(34) {
+            0 => uint(50),
+            n => n,
+        };
+        let icmp = uint(32);
+        let mpls_label = (70..80)
+            .filter_map(|f| set.records.get(&f).and_then(|v| read_uint(v)))
+            .map(|entry| (entry >> 4) as u32)
+            .collect();
+
+        FlowMessage {
+            r#type: FlowType::NetflowV9 as i32,
+            time_received_ns: time_received
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_nanos() as u64)
+                .unwrap_or(0),
+            sequence_num: set.sequence(),
+            sampling_rate,
+            sampler_address: match set.source_ip {
+                Some(IpAddr::V4(ip)) => ip.octets().to_vec(),
+                Some(IpAddr::V6(ip)) => ip.octets().to_vec(),
+                None => Vec::new(),
+            },
+            time_flow_start_ns: ms_to_ns(set.flow_start_millis()),
+            time_flow_end_ns: ms_to_ns(set.flow_end_millis()),
+            bytes: uint(1),
+            packets: uint(2),
+            src_addr: bytes(8, 27),
+            dst_addr: bytes(12, 28),
+            etype: if ipv6 { 0x86dd } else { 0x0800 },
+            proto: uint(4) as u32,
+            src_port: uint(7) as u32,
+            dst_port: uint(11) as u32,
+            in_if: uint(10) as u32,
+            out_if: uint(14) as u32,
+            src_mac: uint(56),
+            dst_mac: uint(80),
+            src_vlan: uint(58) as u32,
+            dst_vlan: uint(59) as u32,
+            ip_tos: uint(5) as u32,
+            forwarding_status: uint(89) as u32,
+            ip_ttl: uint(52) as u32,
+            tcp_flags: uint(6) as u32,
+            icmp_type: (icmp >> 8) as u32,
+            icmp_code: (icmp & 0xff) as u32,
+            ipv6_flow_label: uint(31) as u32,
+            fragment_id: uint(54) as u32,
+            src_as: uint(16) as u32,
+            dst_as: uint(17) as u32,
+            next_hop: bytes(15, 62),
+            src_net: match uint(9) {
+                0 => uint(29) as u32,
+                n => n as u32,
+            },
+            dst_net: match uint(13) {
+                0 => uint(30) as u32,
+                n => n as u32,
+            },
+            bgp_next_hop: bytes(18, 63),
+            mpls_label,
+            observation_domain_id: set.source_id(),
+        }
+    }
+
+    /// Write the message prefixed with its varint length, the
+    /// framing goflow2 uses for files and streams
+    pub fn write_length_delimited<W: io::Write>(
+        &self,
+        mut writer: W,
+    ) -> io::Result<()> {
+        let mut buffer = Vec::with_capacity(self.encoded_len() + 10);
+        // Encoding into a Vec only fails when it runs out of space
+        self.encode_length_delimited(&mut buffer)
+            .map_err(io::Error::other)?;
+        writer.write_all(&buffer)
+    }
+
+    /// Read one length delimited message. Returns `None` when
+    /// the reader is at its end and `InvalidData` for messages
+    /// longer than `MAX_LEN`.
+    pub fn read_length_delimited<R: io::Read>(
+        mut reader: R,
+    ) -> io::Result<Option<Self>> {
+        let mut len: u64 = 0;
+        let mut byte = [0u8; 1];
+        for shift in (0..64).step_by(7) {
+            if reader.read(&mut byte)? == 0 {
+                if shift == 0 {
+                    return Ok(None);
+                }
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            len |= u64::from(byte[0] & 0x7f) << shift;
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+        }
+        if len > FlowMessage::MAX_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "message too long",
+            ));
+        }
+        let mut buffer = vec![0; len as usize];
+        reader.read_exact(&mut buffer)?;
+        FlowMessage::decode(&buffer[..])
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
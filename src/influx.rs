@@ -0,0 +1,192 @@
+use crate::csv::Column;
+use crate::formaters::{read_uint, EnumStyle, KeyStyle};
+use crate::resolve_hashmap::{decode, is_ipv6};
+use crate::templates::field_key;
+use crate::DataFlowset;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::io;
+use std::time::Duration;
+
+#[derive(Debug, Clone)]
+pub struct InfluxConfig {
+    pub measurement: String,
+    /// Columns used as tags. `Column::SourceIp` is named
+    /// `exporter`.
+    pub tags: Vec<Column>,
+    /// Counter fields, summed when aggregating
+    pub fields: Vec<u16>,
+    /// Naming of tag and field keys
+    pub keys: KeyStyle,
+    /// Formatting of enumerated tag values
+    pub enums: EnumStyle,
+    /// Sum the records over intervals of this length instead
+    /// of writing a line per record
+    pub aggregate: Option<Duration>,
+}
+
+impl Default for InfluxConfig {
+    fn default() -> Self {
+        InfluxConfig {
+            measurement: "netflow".to_owned(),
+            tags: vec![Column::SourceIp, Column::Field(10), Column::Field(4)],
+            fields: vec![1, 2],
+            keys: KeyStyle::SnakeCase,
+            enums: EnumStyle::Name,
+            aggregate: None,
+        }
+    }
+}
+
+/// Writes flowsets as InfluxDB line protocol. The timestamp of
+/// a line is the flow end time, or the interval start when
+/// aggregating.
+#[derive(Debug, Clone)]
+pub struct InfluxWriter {
+    config: InfluxConfig,
+    /// Sums per (interval start in ms, tag set)
+    buckets: BTreeMap<(u64, String), Vec<u64>>,
+}
+
+impl InfluxWriter {
+    pub fn new(config: InfluxConfig) -> Self {
+        InfluxWriter {
+            config,
+            buckets: BTreeMap::new(),
+        }
+    }
+
+    /// Write a line for the flowset, or add it to its interval
+    /// when aggregating
+    pub fn write_record<W: io::Write>(
+        &mut self,
+        set: &DataFlowset,
+        mut writer: W,
+    ) -> io::Result<()> {
+        let time = set
+            .flow_end_millis()
+            .unwrap_or(u64::from(set.export_time()) * 1000);
+        let tags = self.tag_set(set);
+        let values: Vec<u64> = self
+            .config
+            .fields
+            .iter()
+            .map(|f| set.records.get(f).and_then(|v| read_uint(v)).unwrap_or(0))
+            .collect();
+
+        match self.config.aggregate {
+            None => self.write_line(&tags, &values, time, &mut writer),
+            Some(interval) => {
+                let interval = millis(interval).max(1);
+                let bucket = self
+                    .buckets
+                    .entry((time - time % interval, tags))
+                    .or_insert_with(|| vec![0; values.len()]);
+                for (sum, v) in bucket.iter_mut().zip(values) {
+                    *sum = sum.saturating_add(v);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Write the aggregated intervals that ended before `now`
+    /// (unix milliseconds)
+    pub fn flush_expired<W: io::Write>(
+        &mut self,
+        now: u64,
+        mut writer: W,
+    ) -> io::Result<()> {
+        let interval = match self.config.aggregate {
+            Some(i) => millis(i),
+            None => return Ok(()),
+        };
+        let expired: Vec<(u64, String)> = self
+            .buckets
+            .keys()
+            .take_while(|(start, _)| start.saturating_add(interval) <= now)
+            .cloned()
+            .collect();
+        for key in expired {
+            let values = self.buckets.remove(&key).unwrap();
+            self.write_line(&key.1, &values, key.0, &mut writer)?;
+        }
+        Ok(())
+    }
+
+    /// Write all the aggregated intervals
+    pub fn flush<W: io::Write>(&mut self, mut writer: W) -> io::Result<()> {
+        let buckets = std::mem::take(&mut self.buckets);
+        for ((start, tags), values) in buckets {
+            self.write_line(&tags, &values, start, &mut writer)?;
+        }
+        Ok(())
+    }
+
+    /// Comma separated `key=value` tags, starting with a comma
+    fn tag_set(&self, set: &DataFlowset) -> String {
+        let ipv6 = is_ipv6(&set.records);
+        let mut tags = String::new();
+        for column in &self.config.tags {
+            let (key, value) = match column {
+                Column::SourceIp => match set.source_ip {
+                    Some(ip) => ("exporter".into(), ip.to_string()),
+                    None => continue,
+                },
+                Column::Field(f) => match set.records.get(f) {
+                    Some(v) => (
+                        field_key(*f, self.config.keys),
+                        decode(*f, v, ipv6)
+                            .with_style(self.config.enums)
+                            .to_string(),
+                    ),
+                    None => continue,
+                },
+            };
+            // Empty tag values are not allowed
+            if value.is_empty() {
+                continue;
+            }
+            tags.push(',');
+            tags.push_str(&escape(&key, ",= "));
+            tags.push('=');
+            tags.push_str(&escape(&value, ",= "));
+        }
+        tags
+    }
+
+    fn write_line<W: io::Write>(
+        &self,
+        tags: &str,
+        values: &[u64],
+        time_ms: u64,
+        writer: &mut W,
+    ) -> io::Result<()> {
+        write!(writer, "{}{}", escape(&self.config.measurement, ", "), tags)?;
+        for (i, (field, value)) in
+            self.config.fields.iter().zip(values).enumerate()
+        {
+            let separator = if i == 0 { ' ' } else { ',' };
+            let key = escape(&field_key(*field, self.config.keys), ",= ");
+            write!(writer, "{}{}={}i", separator, key, value)?;
+        }
+        writeln!(writer, " {}", time_ms.saturating_mul(1_000_000))
+    }
+}
+
+/// Whole milliseconds of `d`, saturating for huge intervals
+fn millis(d: Duration) -> u64 {
+    u64::try_from(d.as_millis()).unwrap_or(u64::MAX)
+}
+
+/// Backslash escape the special characters of line protocol
+fn escape(s: &str, special: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if special.contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
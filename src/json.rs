@@ -0,0 +1,52 @@
+use crate::DataFlowset;
+
+/// Serializes flowsets as newline-delimited JSON into a
+/// buffer that is reused between batches
+#[derive(Debug, Default, Clone)]
+pub struct JsonBatch {
+    buffer: Vec<u8>,
+}
+
+impl JsonBatch {
+    pub fn new() -> Self {
+        JsonBatch { buffer: Vec::new() }
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        JsonBatch {
+            buffer: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// Append one JSON line per flowset to the buffer. On error
+    /// the buffer is left as it was before the call.
+    pub fn write(&mut self, sets: &[DataFlowset]) -> serde_json::Result<()> {
+        let start = self.buffer.len();
+        for set in sets {
+            if let Err(e) = set.write_json(&mut self.buffer) {
+                self.buffer.truncate(start);
+                return Err(e);
+            }
+            self.buffer.push(b'\n');
+        }
+        Ok(())
+    }
+
+    /// Serialized lines written since the last `clear`
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    pub fn len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    /// Empty the buffer, keeping its allocation
+    pub fn clear(&mut self) {
+        self.buffer.clear()
+    }
+}
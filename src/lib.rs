@@ -1,13 +1,75 @@
 #[macro_use]
 extern crate nom;
 
+mod acl;
+#[cfg(feature = "arrow")]
+mod arrow;
+mod binary;
+mod csv;
+mod ecs;
+mod events;
+mod exporter;
 mod formaters;
+#[cfg(feature = "protobuf")]
+mod goflow;
+mod influx;
+mod json;
+#[cfg(feature = "parquet")]
+mod parquet_sink;
+mod pending;
+mod record;
+mod static_templates;
+#[cfg(feature = "tokio")]
+mod stream;
+mod template_cache;
+mod template_limits;
+mod template_snapshot;
+mod template_store;
 mod templates;
+mod text;
 
+pub use acl::{Cidr, ExporterAcl};
+#[cfg(feature = "arrow")]
+pub use arrow::{ArrowBatcher, ArrowConfig, IpRepr};
+pub use binary::Native;
+pub use csv::{Column, CsvWriter};
+pub use events::TemplateEvent;
+pub use formaters::{EnumStyle, FormatOptions, KeyStyle};
+#[cfg(feature = "protobuf")]
+pub use goflow::{FlowMessage, FlowType};
+pub use influx::{InfluxConfig, InfluxWriter};
+pub use json::JsonBatch;
+#[cfg(feature = "parquet")]
+pub use parquet_sink::{ParquetConfig, ParquetSink};
+pub use pending::{PendingLimits, PendingStats};
+pub use record::OwnedFlowRecord;
+pub use static_templates::{StaticTemplate, StaticTemplatePolicy};
+#[cfg(feature = "tokio")]
+pub use stream::{
+    Backpressure, FlowStream, ParseError, ReceivedRecord, StreamConfig,
+};
+pub use template_cache::SharedTemplateCache;
+pub use template_limits::{TemplateLimits, TemplateStats};
+pub use template_snapshot::{TemplateSnapshot, SNAPSHOT_VERSION};
+pub use template_store::{
+    MemoryTemplateStore, Template, TemplateKey, TemplateKind, TemplateStore,
+};
+pub use templates::{field_kind, FieldKind};
+pub use text::TextFormat;
+
+use exporter::{ExporterClocks, RedefinitionRate};
+use formaters::read_uint;
+use pending::PendingQueue;
+use serde::ser::SerializeStruct;
 use serde::Serialize;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, Default)]
 struct NetflowHeader {
     version: u16,
     count: u16,
@@ -29,12 +91,14 @@ struct TemplateHeader {
     field_count: u16,
 }
 
-#[derive(Debug, Clone)]
-struct TemplateField {
-    field: u16,
-    len: u16,
+/// A field of a template: its ID and the length of its values
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TemplateField {
+    pub field: u16,
+    pub len: u16,
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Clone)]
 struct TemplateFlowset {
     tl_header: TypeLenHeader,
@@ -49,6 +113,7 @@ struct OptionTemplateHeader {
     option_len: u16,
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Clone)]
 struct OptionTemplate {
     tl_header: TypeLenHeader,
@@ -56,81 +121,609 @@ struct OptionTemplate {
     payload: Vec<TemplateField>,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone)]
 pub struct DataFlowset<'a> {
     source_ip: Option<&'a std::net::IpAddr>,
-    #[serde(rename = "header")]
     tl_header: TypeLenHeader,
-    #[serde(with = "resolve_hashmap")]
     records: HashMap<u16, &'a [u8]>,
+    options: FormatOptions,
+    export_header: NetflowHeader,
+}
+
+impl<'a> Serialize for DataFlowset<'a> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::ser::Serializer,
+    {
+        let mut state = serializer.serialize_struct("DataFlowset", 3)?;
+        state.serialize_field("source_ip", &self.source_ip)?;
+        state.serialize_field("header", &self.tl_header)?;
+        state.serialize_field(
+            "records",
+            &resolve_hashmap::Records(&self.records, &self.options),
+        )?;
+        state.end()
+    }
 }
 
 mod resolve_hashmap {
-    use crate::templates::TemplateFieldType;
+    use crate::formaters::{fmt_icmpv6, FmtReturn, FormatOptions};
+    use crate::templates::{field_key, TemplateFieldType};
     use serde::ser::{self, SerializeMap};
     use std::collections::HashMap;
 
-    pub fn serialize<'a, S>(
-        hash_map: &HashMap<u16, &'a [u8]>,
+    /// Tell whether the record describes an IPv6 flow. Uses
+    /// the IP version field when present and falls back to
+    /// the address fields.
+    pub fn is_ipv6(hash_map: &HashMap<u16, &[u8]>) -> bool {
+        match hash_map.get(&60) {
+            Some(v) if !v.is_empty() => v[0] == 6,
+            _ => hash_map.contains_key(&27) || hash_map.contains_key(&28),
+        }
+    }
+
+    /// Decode a single field value of a record
+    pub fn decode(field: u16, value: &[u8], ipv6: bool) -> FmtReturn {
+        let record = TemplateFieldType::from(field);
+        let parser = match record {
+            // ICMP type and code names depend on the IP version
+            TemplateFieldType::ICMPType(..) if ipv6 => fmt_icmpv6,
+            _ => record.get_parser(),
+        };
+        parser(value)
+    }
+
+    pub struct Records<'r, 'a>(
+        pub &'r HashMap<u16, &'a [u8]>,
+        pub &'r FormatOptions,
+    );
+
+    impl<'r, 'a> ser::Serialize for Records<'r, 'a> {
+        fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: ser::Serializer,
+        {
+            serialize(self.0, self.1, serializer)
+        }
+    }
+
+    pub fn serialize<S>(
+        hash_map: &HashMap<u16, &[u8]>,
+        options: &FormatOptions,
         serializer: S,
     ) -> Result<S::Ok, S::Error>
     where
         S: ser::Serializer,
     {
+        let ipv6 = is_ipv6(hash_map);
         let mut map = serializer.serialize_map(Some(hash_map.len()))?;
         for (k, v) in hash_map {
-            let record = TemplateFieldType::from(*k);
-            let parser = record.get_parser();
-            let p = parser(v);
-            map.serialize_entry(&record.to_string(), &p)?;
+            let p = decode(*k, v, ipv6).with_style(options.enums);
+            map.serialize_entry(&field_key(*k, options.keys), &p)?;
         }
         map.end()
     }
 }
 
 impl<'a> DataFlowset<'a> {
+    /// Panics if the flowset can't be serialized
+    #[deprecated(note = "use `write_json`, which returns errors")]
     pub fn to_json(&self) -> String {
         serde_json::to_string(&self).unwrap()
     }
 
+    /// Serialize the flowset as JSON straight into `writer`
+    pub fn write_json<W: io::Write>(
+        &self,
+        writer: W,
+    ) -> serde_json::Result<()> {
+        serde_json::to_writer(writer, self)
+    }
+
+    /// Build an Elastic Common Schema document out of the
+    /// flowset
+    pub fn to_ecs(&self) -> serde_json::Value {
+        ecs::to_ecs(self)
+    }
+
+    /// Serialize the flowset as an ECS JSON document into
+    /// `writer`
+    pub fn write_ecs<W: io::Write>(&self, writer: W) -> serde_json::Result<()> {
+        serde_json::to_writer(writer, &self.to_ecs())
+    }
+
+    /// Serializable view with native value types and numeric
+    /// field IDs as keys, meant for binary formats
+    pub fn native(&self) -> Native<'_, 'a> {
+        Native(self)
+    }
+
+    /// Serialize the flowset as MessagePack into `writer`
+    #[cfg(feature = "msgpack")]
+    pub fn write_msgpack<W: io::Write>(
+        &self,
+        mut writer: W,
+    ) -> Result<(), rmp_serde::encode::Error> {
+        rmp_serde::encode::write(&mut writer, &self.native())
+    }
+
+    #[cfg(feature = "msgpack")]
+    pub fn to_msgpack(&self) -> Result<Vec<u8>, rmp_serde::encode::Error> {
+        rmp_serde::to_vec(&self.native())
+    }
+
+    /// Serialize the flowset as CBOR into `writer`
+    #[cfg(feature = "cbor")]
+    pub fn write_cbor<W: io::Write>(
+        &self,
+        writer: W,
+    ) -> Result<(), ciborium::ser::Error<io::Error>> {
+        ciborium::ser::into_writer(&self.native(), writer)
+    }
+
+    #[cfg(feature = "cbor")]
+    pub fn to_cbor(&self) -> Result<Vec<u8>, ciborium::ser::Error<io::Error>> {
+        let mut buffer = Vec::new();
+        self.write_cbor(&mut buffer)?;
+        Ok(buffer)
+    }
+
+    /// Copy the record out of the packet buffer. The result is
+    /// `Send + 'static` and can be handed to other threads.
+    pub fn into_owned(self) -> OwnedFlowRecord {
+        OwnedFlowRecord::from(&self)
+    }
+
+    /// Change how this flowset gets serialized
+    pub fn set_format_options(&mut self, options: FormatOptions) {
+        self.options = options
+    }
+
+    /// Sequence number of the export packet
+    pub fn sequence(&self) -> u32 {
+        self.export_header.sequence
+    }
+
+    /// Source ID (observation domain) of the export packet
+    pub fn source_id(&self) -> u32 {
+        self.export_header.source_id
+    }
+
+    /// Exporter uptime in milliseconds when the packet was sent
+    pub fn sys_uptime(&self) -> u32 {
+        self.export_header.sys_uptime
+    }
+
+    /// Export time of the packet in unix seconds
+    pub fn export_time(&self) -> u32 {
+        self.export_header.timestamp
+    }
+
+    /// Convert a time in exporter uptime milliseconds into
+    /// milliseconds since the unix epoch, based on the export
+    /// time in the packet header
+    pub fn uptime_to_millis(&self, uptime: u32) -> u64 {
+        let header = &self.export_header;
+        let export_ms = i64::from(header.timestamp) * 1000;
+        let offset = i64::from(header.sys_uptime) - i64::from(uptime);
+        (export_ms - offset).max(0) as u64
+    }
+
+    /// Flow start (`First switched`) in unix milliseconds
+    pub fn flow_start_millis(&self) -> Option<u64> {
+        self.uptime_field_millis(22)
+    }
+
+    /// Flow end (`Last Switched`) in unix milliseconds
+    pub fn flow_end_millis(&self) -> Option<u64> {
+        self.uptime_field_millis(21)
+    }
+
+    fn uptime_field_millis(&self, field: u16) -> Option<u64> {
+        let uptime = self.records.get(&field).and_then(|v| read_uint(v))?;
+        Some(self.uptime_to_millis(uptime as u32))
+    }
+
     fn set_source_ip(&mut self, addr: &'a std::net::IpAddr) {
         self.source_ip = Some(addr)
     }
+
+    fn set_export_header(&mut self, header: NetflowHeader) {
+        self.export_header = header
+    }
 }
 
+/// NetFlow v9 packet parser. Templates are kept in a
+/// `TemplateStore`, by default a map owned by the parser.
 #[derive(Clone)]
-pub struct Parser {
-    template_cache: HashMap<u16, TemplateFlowset>,
-    options_cache: HashMap<u16, OptionTemplate>,
+pub struct Parser<S: TemplateStore = MemoryTemplateStore> {
+    templates: S,
+    /// Templates defined ahead of time, used when the store has
+    /// none for the key
+    static_templates: HashMap<TemplateKey, Arc<Template>>,
+    static_policy: StaticTemplatePolicy,
+    /// Static templates already reported as contradicted by the
+    /// exporter
+    static_mismatches: HashSet<TemplateKey>,
+    events: VecDeque<TemplateEvent>,
+    /// Exporters packets are accepted from, all by default
+    acl: Option<ExporterAcl>,
+    /// Exporters whose templates can't be redefined
+    pinned: Vec<Cidr>,
+    redefinitions: Option<RedefinitionRate>,
+    /// Templates not refreshed within this long are removed
+    template_timeout: Option<Duration>,
+    /// Last time expired templates were looked for
+    expired: Instant,
+    /// Last packet of each exporter and source ID
+    exporters: ExporterClocks,
+    /// Data flowsets waiting for their template
+    pending: Option<PendingQueue>,
+    format_options: FormatOptions,
+}
+
+impl<S: TemplateStore + Default> Default for Parser<S> {
+    fn default() -> Self {
+        Parser::with_store(S::default())
+    }
 }
 
 impl Parser {
     pub fn new() -> Self {
+        Parser::with_store(MemoryTemplateStore::new())
+    }
+
+    /// Parser using a template cache shared with other parsers,
+    /// typically one per receive thread
+    pub fn with_shared_cache(
+        cache: SharedTemplateCache,
+    ) -> Parser<SharedTemplateCache> {
+        Parser::with_store(cache)
+    }
+}
+
+impl<S: TemplateStore> Parser<S> {
+    pub fn with_store(templates: S) -> Self {
         Parser {
-            template_cache: HashMap::new(),
-            options_cache: HashMap::new(),
+            templates,
+            static_templates: HashMap::new(),
+            static_policy: StaticTemplatePolicy::default(),
+            static_mismatches: HashSet::new(),
+            events: VecDeque::new(),
+            acl: None,
+            pinned: Vec::new(),
+            redefinitions: None,
+            template_timeout: None,
+            expired: Instant::now(),
+            exporters: ExporterClocks::new(),
+            pending: None,
+            format_options: FormatOptions::default(),
+        }
+    }
+
+    /// The template store of the parser
+    pub fn templates(&self) -> &S {
+        &self.templates
+    }
+
+    pub fn templates_mut(&mut self) -> &mut S {
+        &mut self.templates
+    }
+
+    /// Decode the records of `template.key()` with `template`
+    /// until the exporter sends its own
+    pub fn add_static_template(&mut self, template: &StaticTemplate) {
+        self.static_mismatches.remove(&template.key());
+        self.static_templates
+            .insert(template.key(), Arc::new(template.template()));
+    }
+
+    /// Set what received templates do to static ones, by
+    /// default they override them
+    pub fn set_static_policy(&mut self, policy: StaticTemplatePolicy) {
+        self.static_policy = policy
+    }
+
+    /// Take the events that happened since the last call
+    pub fn drain_events(&mut self) -> impl Iterator<Item = TemplateEvent> + '_ {
+        self.events.drain(..)
+    }
+
+    fn push_event(&mut self, event: TemplateEvent) {
+        if self.events.len() == events::MAX_EVENTS {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+
+    /// Only accept packets from the exporters `acl` allows,
+    /// `parse_netflow_packet` fails for the others
+    pub fn set_exporter_acl(&mut self, acl: ExporterAcl) {
+        self.acl = Some(acl)
+    }
+
+    /// Keep the first template received for each key from the
+    /// exporters in `networks`. Redefinitions and withdrawals are
+    /// ignored and reported as `TemplateEvent::PinnedRedefinition`
+    /// since anyone can send packets with the address of an
+    /// exporter; restarts of these exporters don't flush their
+    /// templates either. The template timeout and the limits of
+    /// the store still apply.
+    pub fn pin_exporters(&mut self, networks: Vec<Cidr>) {
+        self.pinned = networks
+    }
+
+    fn is_pinned(&self, exporter: &IpAddr) -> bool {
+        self.pinned.iter().any(|n| n.contains(exporter))
+    }
+
+    /// Report templates redefined more than `max` times within
+    /// `window` as `TemplateEvent::RedefinitionRate`
+    pub fn set_redefinition_limit(&mut self, max: u32, window: Duration) {
+        self.redefinitions = Some(RedefinitionRate::new(max, window))
+    }
+
+    /// Remove the templates an exporter did not refresh within
+    /// `timeout`. By default templates are kept until replaced.
+    pub fn set_template_timeout(&mut self, timeout: Option<Duration>) {
+        self.template_timeout = timeout
+    }
+
+    /// Remove the templates received from `exporter` with
+    /// `source_id`, returning how many were removed
+    pub fn flush_exporter(
+        &mut self,
+        exporter: IpAddr,
+        source_id: u32,
+    ) -> usize {
+        let keys: Vec<TemplateKey> = self
+            .templates
+            .iter()
+            .map(|(key, _)| key)
+            .filter(|key| {
+                key.exporter == exporter && key.source_id == source_id
+            })
+            .collect();
+        for key in &keys {
+            self.templates.withdraw(key);
+        }
+        if let Some(pending) = &mut self.pending {
+            pending.discard(exporter, source_id);
+        }
+        keys.len()
+    }
+
+    /// Flush the templates of the exporter when it restarted,
+    /// it may reuse template IDs for other layouts
+    fn check_restart(
+        &mut self,
+        exporter: IpAddr,
+        header: &NetflowHeader,
+        now: Instant,
+    ) {
+        let source_id = header.source_id;
+        let restarted = match self.exporters.get_mut(&exporter, source_id) {
+            Some(clock) => clock.update(header),
+            None => {
+                let max_exporters = self.templates.limits().max_exporters;
+                if self.exporters.is_full(&exporter, max_exporters) {
+                    self.exporters.mark_stale();
+                    self.prune_clocks(now);
+                }
+                self.exporters.insert(exporter, header, max_exporters);
+                false
+            }
+        };
+        if restarted {
+            let flushed = if self.is_pinned(&exporter) {
+                0
+            } else {
+                self.flush_exporter(exporter, source_id)
+            };
+            self.push_event(TemplateEvent::ExporterRestarted {
+                exporter,
+                source_id,
+                flushed,
+            });
+        }
+    }
+
+    /// Drop the clocks of the exporters and source IDs left
+    /// without templates, once a second at most
+    fn prune_clocks(&mut self, now: Instant) {
+        if !self.exporters.prune_due(now) {
+            return;
+        }
+        let live: HashSet<(IpAddr, u32)> = self
+            .templates
+            .iter()
+            .map(|(key, _)| (key.exporter, key.source_id))
+            .collect();
+        self.exporters.retain(&live, now);
+    }
+
+    /// Remove the templates past the template timeout, once a
+    /// second at most
+    fn expire_templates(&mut self, now: Instant) {
+        let timeout = match self.template_timeout {
+            Some(timeout) => timeout,
+            None => return,
+        };
+        if now.duration_since(self.expired)
+            < timeout.min(Duration::from_secs(1))
+        {
+            return;
+        }
+        self.expired = now;
+        let before = SystemTime::now().checked_sub(timeout);
+        let count = before.map_or(0, |before| self.templates.expire(before));
+        if count > 0 {
+            self.exporters.mark_stale();
+            self.push_event(TemplateEvent::Expired { count });
+        }
+    }
+
+    /// Keep data flowsets whose template is unknown, within
+    /// `limits`, and decode them once it arrives. By default
+    /// they are dropped. The records are returned by
+    /// `take_replayed` rather than by `parse_netflow_packet`.
+    pub fn set_pending_limits(&mut self, limits: PendingLimits) {
+        self.pending = Some(PendingQueue::new(limits))
+    }
+
+    /// Counters of the flowsets waiting for their template
+    pub fn pending_stats(&self) -> PendingStats {
+        self.pending
+            .as_ref()
+            .map_or_else(PendingStats::default, PendingQueue::stats)
+    }
+
+    /// Take the records of the flowsets decoded since the last
+    /// call because their template arrived, see
+    /// `set_pending_limits`
+    pub fn take_replayed(&mut self) -> Vec<OwnedFlowRecord> {
+        self.pending
+            .as_mut()
+            .map_or_else(Vec::new, PendingQueue::take_replayed)
+    }
+
+    fn replay(&mut self, key: &TemplateKey, template: &Template) {
+        if let Some(pending) = &mut self.pending {
+            if !pending.is_empty() {
+                pending.replay(key, template);
+            }
+        }
+    }
+
+    /// Store a template received from an exporter. One without
+    /// fields withdraws the template.
+    fn learn_template(&mut self, key: TemplateKey, template: Template) {
+        let pinned = self.is_pinned(&key.exporter);
+        if template.fields.is_empty() {
+            if pinned {
+                if self.templates.lookup(&key).is_some() {
+                    self.push_event(TemplateEvent::PinnedRedefinition { key });
+                }
+            } else if self.templates.withdraw(&key).is_some() {
+                self.push_event(TemplateEvent::Withdrawn { key });
+            }
+            return;
+        }
+        if let Some(known) = self.static_templates.get(&key) {
+            let overridden =
+                self.static_policy == StaticTemplatePolicy::Override;
+            if !known.same_layout(&template) {
+                // Reported once, an overridden static template is no
+                // longer a fallback once the received one is gone
+                if overridden {
+                    self.static_templates.remove(&key);
+                }
+                if self.static_mismatches.insert(key) {
+                    self.push_event(TemplateEvent::StaticMismatch {
+                        key,
+                        overridden,
+                    });
+                }
+            }
+            if !overridden {
+                return;
+            }
+        }
+        let known = self.templates.lookup(&key);
+        if let Some(known) = &known {
+            if !known.same_layout(&template) {
+                if pinned {
+                    self.push_event(TemplateEvent::PinnedRedefinition { key });
+                    return;
+                }
+                self.push_event(TemplateEvent::Redefined { key });
+                if let Some(rate) = &mut self.redefinitions {
+                    if let Some(count) = rate.redefined(key) {
+                        let window = rate.window();
+                        self.push_event(TemplateEvent::RedefinitionRate {
+                            key,
+                            count,
+                            window,
+                        });
+                    }
+                }
+            }
+        }
+        let template = Arc::new(template);
+        // Templates evicted or rejected by a bounded store
+        // may leave exporters without templates
+        let bounded = self.templates.limits() != TemplateLimits::default();
+        let before = if bounded { self.templates.len() } else { 0 };
+        self.templates.insert(key, template.clone());
+        if bounded
+            && self.templates.len() < before + usize::from(known.is_none())
+        {
+            self.exporters.mark_stale();
         }
+        self.replay(&key, &template);
+    }
+
+    fn template(&mut self, key: &TemplateKey) -> Option<Arc<Template>> {
+        self.templates
+            .lookup(key)
+            .or_else(|| self.static_templates.get(key).cloned())
+    }
+
+    /// Save the templates to `path`, to be restored with
+    /// `load_templates` after a restart
+    pub fn save_templates<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        TemplateSnapshot::capture(&self.templates).save(path)
+    }
+
+    /// Restore the templates saved to `path`, except those last
+    /// received more than `max_age` ago. Returns the number of
+    /// templates restored.
+    pub fn load_templates<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        max_age: Option<Duration>,
+    ) -> io::Result<usize> {
+        let snapshot = TemplateSnapshot::load(path)?;
+        Ok(snapshot.restore(&mut self.templates, max_age))
+    }
+
+    /// Set the serialization options applied to every
+    /// `DataFlowset` returned by the parser
+    pub fn set_format_options(&mut self, options: FormatOptions) {
+        self.format_options = options
     }
 
-    pub fn parse_netflow_packet<'a, 'b>(
-        &'a mut self,
+    pub fn parse_netflow_packet<'b>(
+        &mut self,
         packet: &'b [u8],
-        addr: &'b std::net::IpAddr,
+        addr: &'b IpAddr,
     ) -> Result<Vec<DataFlowset<'b>>, &'static str> {
+        if self.acl.as_ref().is_some_and(|acl| !acl.allows(addr)) {
+            return Err("Exporter not allowed");
+        }
         //20 bytes Netflow packet header
         let mut data = packet;
+        let now = Instant::now();
+        self.expire_templates(now);
+        self.prune_clocks(now);
+        if let Some(pending) = &mut self.pending {
+            pending.expire(now);
+        }
 
         if let Ok((buffer, header)) = parse_netflow_header(data) {
             match header.version {
                 9 => {}
                 _ => return Err("Unrecognized version"),
             }
+            self.check_restart(*addr, &header, now);
 
             data = buffer;
 
             let mut flowset_count = header.count;
             let mut data_flowsets = Vec::<DataFlowset>::new();
-            loop {
+            // Stop at the advertised count or once the data runs out,
+            // whichever comes first
+            while flowset_count > 0 && !data.is_empty() {
                 if let Ok((buffer, tl_header)) = parse_tl_header(data) {
                     data = buffer;
 
@@ -141,11 +734,20 @@ impl Parser {
                                 parse_template(data, tl_header)
                             {
                                 data = buffer;
-                                self.template_cache.insert(
-                                    template_flowset
+                                let key = TemplateKey {
+                                    exporter: *addr,
+                                    source_id: header.source_id,
+                                    template_id: template_flowset
                                         .template_header
                                         .template_id,
-                                    template_flowset,
+                                };
+                                self.learn_template(
+                                    key,
+                                    Template {
+                                        kind: TemplateKind::Data,
+                                        fields: template_flowset.payload,
+                                        updated: SystemTime::now(),
+                                    },
                                 );
                             } else {
                                 return Err("Failed to parse the template");
@@ -158,11 +760,25 @@ impl Parser {
                                 parse_options_template(data, tl_header)
                             {
                                 data = buffer;
-                                self.options_cache.insert(
-                                    template_flowset
+                                let key = TemplateKey {
+                                    exporter: *addr,
+                                    source_id: header.source_id,
+                                    template_id: template_flowset
                                         .options_template_header
                                         .template_id,
-                                    template_flowset,
+                                };
+                                let header =
+                                    &template_flowset.options_template_header;
+                                let kind = TemplateKind::Options {
+                                    scope_fields: header.scope_len / 4,
+                                };
+                                self.learn_template(
+                                    key,
+                                    Template {
+                                        kind,
+                                        fields: template_flowset.payload,
+                                        updated: SystemTime::now(),
+                                    },
                                 );
                             } else {
                                 return Err(
@@ -177,44 +793,66 @@ impl Parser {
                                 return Err("Flowset ID out of range");
                             }
                             // Get the template fromthe cache
-                            if self
-                                .template_cache
-                                .contains_key(&tl_header.flowset_id)
-                            {
-                                let template = {
-                                    self.template_cache
-                                        .get(&tl_header.flowset_id)
-                                        .unwrap()
-                                };
+                            let key = TemplateKey {
+                                exporter: *addr,
+                                source_id: header.source_id,
+                                template_id: tl_header.flowset_id,
+                            };
+                            let template = self.template(&key);
+                            let known = template.is_some();
+                            let template = template
+                                .filter(|t| t.kind == TemplateKind::Data);
+                            if let Some(template) = template {
+                                // The template may have come from a
+                                // shared store or a snapshot
+                                self.replay(&key, &template);
                                 if let Ok((buffer, flowsets)) =
                                     parse_dataset(data, tl_header, &template)
                                 {
                                     data = buffer;
                                     for mut f in flowsets {
                                         f.set_source_ip(addr);
+                                        f.set_export_header(header);
+                                        f.set_format_options(
+                                            self.format_options,
+                                        );
                                         data_flowsets.push(f);
                                     }
                                 } else {
                                     return Err("Failed to parse the dataset");
                                 }
-                            } else if self
-                                .options_cache
-                                .contains_key(&tl_header.flowset_id)
-                            {
-                                data = &data[(tl_header.length - 4) as usize..];
                             } else {
-                                data = &data[(tl_header.length - 4) as usize..];
+                                // Options data and flowsets with unknown
+                                // templates are skipped, the latter
+                                // kept for later if enabled
+                                let length = match (tl_header.length as usize)
+                                    .checked_sub(4)
+                                    .filter(|l| *l <= data.len())
+                                {
+                                    Some(length) => length,
+                                    None => {
+                                        return Err("Invalid flowset length")
+                                    }
+                                };
+                                if let (false, Some(pending)) =
+                                    (known, &mut self.pending)
+                                {
+                                    pending.push(
+                                        key,
+                                        header,
+                                        tl_header,
+                                        &data[..length],
+                                    );
+                                }
+                                data = &data[length..];
                             }
                         }
                     }
                 }
                 // we failed to parse it so just try another one
                 flowset_count -= 1;
-
-                if flowset_count == 0 {
-                    return Ok(data_flowsets);
-                }
             }
+            return Ok(data_flowsets);
         }
         Err("Failed to parse header")
     }
@@ -230,9 +868,9 @@ named!(parse_netflow_header<&[u8], NetflowHeader>, do_parse!(
             version: version_and_count.0,
             count: version_and_count.1,
             sys_uptime: uptime,
-            timestamp: timestamp,
+            timestamp,
             sequence: seq,
-            source_id: source_id
+            source_id
         })
 ));
 
@@ -240,8 +878,8 @@ named!(parse_tl_header<&[u8], TypeLenHeader>, do_parse!(
     flowset_id: bits!(take_bits!(16u16)) >>
         length: bits!(take_bits!(16u16)) >>
         (TypeLenHeader {
-            flowset_id: flowset_id,
-            length: length
+            flowset_id,
+            length
         })
 ));
 
@@ -249,8 +887,8 @@ named!(parse_template_header<&[u8], TemplateHeader>, do_parse!(
     template_id: bits!(take_bits!(16u16)) >>
        field_count: bits!(take_bits!(16u16)) >>
         (TemplateHeader {
-            template_id: template_id,
-            field_count: field_count
+            template_id,
+            field_count
         })
 ));
 
@@ -258,8 +896,8 @@ named!(parse_template_fields<&[u8], TemplateField>, do_parse!(
     field: bits!(take_bits!(16u16)) >>
         len: bits!(take_bits!(16u16)) >>
         (TemplateField {
-            field: field,
-            len: len
+            field,
+            len
         })
 ));
 
@@ -268,91 +906,90 @@ named!(parse_option_template_header<&[u8], OptionTemplateHeader>, do_parse!(
         scope_len: bits!(take_bits!(16u16)) >>
         option_len: bits!(take_bits!(16u16)) >>
         (OptionTemplateHeader {
-            template_id: template_id,
-            scope_len: scope_len,
-            option_len: option_len
+            template_id,
+            scope_len,
+            option_len
         })
 ));
 
-fn parse_template<'a>(
-    mut buffer: &'a [u8],
+fn parse_template(
+    buffer: &[u8],
     tl_header: TypeLenHeader,
-) -> nom::IResult<&'a [u8], TemplateFlowset, ()> {
+) -> nom::IResult<&[u8], TemplateFlowset, ()> {
     // Adjust for TypeLenHeader size already parsed
-    let mut byte_count = tl_header.length as usize - 4;
+    let length = (tl_header.length as usize)
+        .checked_sub(4)
+        .filter(|l| *l <= buffer.len())
+        .ok_or(nom::Err::Error(()))?;
+    let (mut body, rest) = buffer.split_at(length);
     // Keep parsed templates fields locally
     let mut template_fields: Vec<TemplateField> = Vec::new();
 
-    if let Ok((bytes, template_header)) = parse_template_header(buffer) {
-        // Keep a track of bytes to parse but the
-        // "field_count" should be enough to parse it correctly
-        byte_count -= buffer.len() - bytes.len();
+    if let Ok((bytes, template_header)) = parse_template_header(body) {
+        // The "field_count" must account for the whole flowset
+        if usize::from(template_header.field_count) * 4 != bytes.len() {
+            return Err(nom::Err::Error(()));
+        }
         // Ensure the correct buffer is parsed
-        buffer = bytes;
-        let field_count = template_header.field_count;
+        body = bytes;
 
-        for _ in 0..field_count {
-            if let Ok((bytes, template_field)) = parse_template_fields(buffer) {
-                byte_count -= buffer.len() - bytes.len();
-                buffer = bytes;
+        for _ in 0..template_header.field_count {
+            if let Ok((bytes, template_field)) = parse_template_fields(body) {
+                body = bytes;
                 template_fields.push(template_field);
             } else {
                 return Err(nom::Err::Failure(()));
             }
         }
-        if byte_count == 0 {
-            // Looks like we parsed all fields so add the template if we dont have it already
-            return Ok((
-                buffer,
-                TemplateFlowset {
-                    tl_header,
-                    template_header,
-                    payload: template_fields,
-                },
-            ));
-        }
+        // Looks like we parsed all fields so add the template if we dont have it already
+        return Ok((
+            rest,
+            TemplateFlowset {
+                tl_header,
+                template_header,
+                payload: template_fields,
+            },
+        ));
     }
     Err(nom::Err::Error(()))
 }
 
-fn parse_options_template<'a>(
-    mut buffer: &'a [u8],
+fn parse_options_template(
+    buffer: &[u8],
     tl_header: TypeLenHeader,
-) -> nom::IResult<&'a [u8], OptionTemplate, ()> {
+) -> nom::IResult<&[u8], OptionTemplate, ()> {
+    // Adjust for TypeLenHeader size already parsed
+    let length = (tl_header.length as usize)
+        .checked_sub(4)
+        .filter(|l| *l <= buffer.len())
+        .ok_or(nom::Err::Error(()))?;
+    let (mut body, rest) = buffer.split_at(length);
     let mut template_fields: Vec<TemplateField> = Vec::new();
-    let mut byte_count = 4; //Adjust for header length
-    if let Ok((bytes, template_header)) = parse_option_template_header(buffer) {
-        byte_count += buffer.len() - bytes.len();
-        // Ensure the correct buffer is parsed
-        buffer = bytes;
-
-        // Parse first the scope fields
-        let scope_len = template_header.scope_len / 4; // len is in bytes we're parsing 2 x u16
-        for _ in 0..scope_len {
-            if let Ok((bytes, template_field)) = parse_template_fields(buffer) {
-                byte_count += buffer.len() - bytes.len();
-                buffer = bytes;
-                template_fields.push(template_field);
-            } else {
-                return Err(nom::Err::Failure(()));
-            }
+    if let Ok((bytes, template_header)) = parse_option_template_header(body) {
+        // len is in bytes we're parsing 2 x u16
+        let scope_len = template_header.scope_len / 4;
+        let option_len = template_header.option_len / 4;
+        // The fields must fit in the flowset, anything after them is padding
+        if (usize::from(scope_len) + usize::from(option_len)) * 4 > bytes.len()
+        {
+            return Err(nom::Err::Error(()));
         }
+        // Ensure the correct buffer is parsed
+        body = bytes;
 
-        // ...then the option fields
-        let option_len = template_header.option_len / 4;
-        for _ in 0..option_len {
-            if let Ok((bytes, template_field)) = parse_template_fields(buffer) {
-                byte_count += buffer.len() - bytes.len();
-                buffer = bytes;
+        // Parse first the scope fields, then the option fields
+        for _ in 0..scope_len + option_len {
+            if let Ok((bytes, template_field)) = parse_template_fields(body) {
+                body = bytes;
                 template_fields.push(template_field);
             } else {
                 return Err(nom::Err::Failure(()));
             }
         }
 
-        // Adjust for any remaining padding
+        // Skip any remaining padding
         return Ok((
-            &buffer[tl_header.length as usize - byte_count..],
+            rest,
             OptionTemplate {
                 tl_header,
                 options_template_header: template_header,
@@ -366,13 +1003,19 @@ fn parse_options_template<'a>(
 fn parse_dataset<'a>(
     buffer: &'a [u8],
     tl_header: TypeLenHeader,
-    template: &TemplateFlowset,
+    template: &Template,
 ) -> nom::IResult<&'a [u8], Vec<DataFlowset<'a>>, ()> {
     let mut dataflows = Vec::new();
     let mut idx: usize = 0;
-    let fields = &template.payload;
-    let length = tl_header.length as usize - 4;
-    while idx < length {
+    let fields = &template.fields;
+    let length = (tl_header.length as usize)
+        .checked_sub(4)
+        .filter(|l| *l <= buffer.len())
+        .ok_or(nom::Err::Error(()))?;
+    // Whatever is left once a record no longer fits is padding,
+    // or the template does not match the data
+    let record_len = template.record_len();
+    while record_len > 0 && length - idx >= record_len {
         let mut records = HashMap::new();
         for f in fields {
             let value = &buffer[idx..idx + f.len as usize];
@@ -383,6 +1026,8 @@ fn parse_dataset<'a>(
             source_ip: None,
             tl_header,
             records,
+            options: FormatOptions::default(),
+            export_header: NetflowHeader::default(),
         })
     }
     // Adjust for possible remaining padding
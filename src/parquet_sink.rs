@@ -0,0 +1,242 @@
+use crate::arrow::{ArrowBatcher, ArrowConfig};
+use crate::csv::Column;
+use crate::DataFlowset;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::errors::ParquetError;
+use parquet::file::properties::WriterProperties;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone)]
+pub struct ParquetConfig {
+    /// Where the files are written
+    pub directory: PathBuf,
+    /// Length of the time window covered by one file. Windows
+    /// are aligned on multiples of this period.
+    pub rotate_every: Duration,
+    pub compression: Compression,
+    /// Maximum number of rows per row group
+    pub row_group_size: usize,
+    /// Maximum number of files open at once. Beyond it the
+    /// least recently written one is closed, later flows of its
+    /// exporter and window go to a new file.
+    pub max_open_files: usize,
+    /// Column naming, IP representation and in-memory batching
+    pub arrow: ArrowConfig,
+}
+
+impl Default for ParquetConfig {
+    fn default() -> Self {
+        ParquetConfig {
+            directory: PathBuf::from("."),
+            rotate_every: Duration::from_secs(5 * 60),
+            compression: Compression::SNAPPY,
+            row_group_size: 1024 * 1024,
+            max_open_files: 256,
+            arrow: ArrowConfig::default(),
+        }
+    }
+}
+
+struct OpenFile {
+    writer: ArrowWriter<File>,
+    batcher: ArrowBatcher,
+    window_start: u64,
+    /// Tick of the last write, to find the least recently
+    /// written file
+    written: u64,
+}
+
+/// Writes flowsets to Parquet files, one file per exporter and
+/// time window, named `flows-<exporter>-<start>.parquet` where
+/// `<start>` is the window start in unix seconds. Existing
+/// files are not overwritten, `-<n>` is added to the name
+/// instead.
+///
+/// Open files are closed when the sink is dropped, ignoring
+/// errors. Call `close` to get them.
+pub struct ParquetSink {
+    columns: Vec<Column>,
+    config: ParquetConfig,
+    files: HashMap<Option<IpAddr>, OpenFile>,
+    tick: u64,
+}
+
+impl ParquetSink {
+    pub fn new(columns: Vec<Column>, config: ParquetConfig) -> Self {
+        ParquetSink {
+            columns,
+            config,
+            files: HashMap::new(),
+            tick: 0,
+        }
+    }
+
+    pub fn write(&mut self, set: &DataFlowset) -> Result<(), ParquetError> {
+        self.write_at(set, SystemTime::now())
+    }
+
+    /// Write a flowset as received at time `now`
+    pub fn write_at(
+        &mut self,
+        set: &DataFlowset,
+        now: SystemTime,
+    ) -> Result<(), ParquetError> {
+        let window = self.window_start(now);
+        let exporter = set.source_ip.copied();
+
+        if let Some(file) = self.files.get(&exporter) {
+            if file.window_start != window {
+                let file = self.files.remove(&exporter).unwrap();
+                close(file)?;
+            }
+        }
+        if !self.files.contains_key(&exporter) {
+            if self.files.len() >= self.config.max_open_files.max(1) {
+                let oldest = self
+                    .files
+                    .iter()
+                    .min_by_key(|(_, f)| f.written)
+                    .map(|(k, _)| *k)
+                    .unwrap();
+                close(self.files.remove(&oldest).unwrap())?;
+            }
+            let file = self.open(exporter, window)?;
+            self.files.insert(exporter, file);
+        }
+
+        self.tick += 1;
+        let file = self.files.get_mut(&exporter).unwrap();
+        file.written = self.tick;
+        if let Some(batch) = file.batcher.push(set)? {
+            file.writer.write(&batch)?;
+        }
+        Ok(())
+    }
+
+    /// Close the files whose time window is over. Meant to be
+    /// called periodically so that files get finalized even
+    /// when an exporter goes quiet.
+    pub fn rotate(&mut self) -> Result<(), ParquetError> {
+        self.rotate_at(SystemTime::now())
+    }
+
+    pub fn rotate_at(&mut self, now: SystemTime) -> Result<(), ParquetError> {
+        let window = self.window_start(now);
+        let expired: Vec<Option<IpAddr>> = self
+            .files
+            .iter()
+            .filter(|(_, f)| f.window_start != window)
+            .map(|(k, _)| *k)
+            .collect();
+        for exporter in expired {
+            close(self.files.remove(&exporter).unwrap())?;
+        }
+        Ok(())
+    }
+
+    /// Flush and finalize all open files
+    pub fn close(mut self) -> Result<(), ParquetError> {
+        let mut result = Ok(());
+        for (_, file) in self.files.drain() {
+            let closed = close(file);
+            if result.is_ok() {
+                result = closed;
+            }
+        }
+        result
+    }
+
+    /// Path of the file holding flows of `exporter` for the
+    /// window starting at `window_start`
+    pub fn file_path(
+        &self,
+        exporter: Option<IpAddr>,
+        window_start: u64,
+    ) -> PathBuf {
+        let exporter = match exporter {
+            Some(ip) => ip.to_string().replace(':', "_"),
+            None => "unknown".to_owned(),
+        };
+        self.config
+            .directory
+            .join(format!("flows-{}-{}.parquet", exporter, window_start))
+    }
+
+    fn window_start(&self, now: SystemTime) -> u64 {
+        let now = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let period = self.config.rotate_every.as_secs().max(1);
+        now - now % period
+    }
+
+    fn open(
+        &self,
+        exporter: Option<IpAddr>,
+        window_start: u64,
+    ) -> Result<OpenFile, ParquetError> {
+        let batcher =
+            ArrowBatcher::new(self.columns.clone(), self.config.arrow.clone());
+        let properties = WriterProperties::builder()
+            .set_compression(self.config.compression)
+            .set_max_row_group_size(self.config.row_group_size)
+            .build();
+        let file = self.create(exporter, window_start)?;
+        let writer =
+            ArrowWriter::try_new(file, batcher.schema(), Some(properties))?;
+        Ok(OpenFile {
+            writer,
+            batcher,
+            window_start,
+            written: 0,
+        })
+    }
+
+    /// Create the file of `exporter` for the window, with a
+    /// suffix if it already exists
+    fn create(
+        &self,
+        exporter: Option<IpAddr>,
+        window_start: u64,
+    ) -> io::Result<File> {
+        let path = self.file_path(exporter, window_start);
+        let stem = path.with_extension("");
+        let mut candidate = path;
+        let mut n = 0;
+        loop {
+            match OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&candidate)
+            {
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    n += 1;
+                    let mut name = stem.clone().into_os_string();
+                    name.push(format!("-{}.parquet", n));
+                    candidate = PathBuf::from(name);
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+impl Drop for ParquetSink {
+    fn drop(&mut self) {
+        for (_, file) in self.files.drain() {
+            let _ = close(file);
+        }
+    }
+}
+
+fn close(mut file: OpenFile) -> Result<(), ParquetError> {
+    if let Some(batch) = file.batcher.flush()? {
+        file.writer.write(&batch)?;
+    }
+    file.writer.close()?;
+    Ok(())
+}
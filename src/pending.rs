@@ -0,0 +1,224 @@
+use crate::record::OwnedFlowRecord;
+use crate::template_store::{Template, TemplateKey, TemplateKind};
+use crate::{parse_dataset, NetflowHeader, TypeLenHeader};
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+/// How many data flowsets with an unknown template a `Parser`
+/// keeps, waiting for their template. See
+/// `Parser::set_pending_limits`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PendingLimits {
+    /// Total size of the flowsets kept, whether waiting for
+    /// their template or decoded and waiting for
+    /// `Parser::take_replayed`. The oldest decoded ones are
+    /// dropped first, then further flowsets until room is made.
+    pub max_bytes: usize,
+    /// Flowsets whose template did not arrive within this long
+    /// are dropped
+    pub max_age: Duration,
+}
+
+impl Default for PendingLimits {
+    fn default() -> Self {
+        PendingLimits {
+            max_bytes: 1 << 20,
+            max_age: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Counters of the flowsets waiting for their template
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PendingStats {
+    /// Flowsets currently waiting
+    pub pending: usize,
+    /// Size of the flowsets currently waiting
+    pub bytes: usize,
+    /// Size of the flowsets decoded but not taken yet
+    pub unclaimed: usize,
+    /// Flowsets decoded once their template arrived
+    pub replayed: u64,
+    /// Flowsets dropped because their template did not arrive
+    /// in time
+    pub expired: u64,
+    /// Flowsets dropped because the queue was full, because
+    /// their template turned out to be an options template,
+    /// because their exporter restarted or because their records
+    /// were not taken in time
+    pub dropped: u64,
+}
+
+#[derive(Debug, Clone)]
+struct PendingFlowset {
+    exporter: IpAddr,
+    header: NetflowHeader,
+    tl_header: TypeLenHeader,
+    data: Box<[u8]>,
+    received: Instant,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct PendingQueue {
+    limits: PendingLimits,
+    flowsets: HashMap<TemplateKey, VecDeque<PendingFlowset>>,
+    stats: PendingStats,
+    /// Records of the decoded flowsets along with the size of
+    /// the flowsets, oldest first
+    replayed: VecDeque<(usize, Vec<OwnedFlowRecord>)>,
+    /// Last time expired flowsets were looked for
+    expired: Instant,
+}
+
+impl PendingQueue {
+    pub(crate) fn new(limits: PendingLimits) -> Self {
+        PendingQueue {
+            limits,
+            flowsets: HashMap::new(),
+            stats: PendingStats::default(),
+            replayed: VecDeque::new(),
+            expired: Instant::now(),
+        }
+    }
+
+    pub(crate) fn stats(&self) -> PendingStats {
+        self.stats
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.flowsets.is_empty()
+    }
+
+    /// Keep the body of a data flowset, `data`, until the
+    /// template of `key` arrives
+    pub(crate) fn push(
+        &mut self,
+        key: TemplateKey,
+        header: NetflowHeader,
+        tl_header: TypeLenHeader,
+        data: &[u8],
+    ) {
+        if !self.make_room(data.len()) {
+            self.stats.dropped += 1;
+            return;
+        }
+        self.stats.pending += 1;
+        self.stats.bytes += data.len();
+        self.flowsets
+            .entry(key)
+            .or_default()
+            .push_back(PendingFlowset {
+                exporter: key.exporter,
+                header,
+                tl_header,
+                data: data.into(),
+                received: Instant::now(),
+            });
+    }
+
+    /// Drop the oldest decoded flowsets until `len` more bytes
+    /// fit, returning whether they do
+    fn make_room(&mut self, len: usize) -> bool {
+        while self.stats.bytes + self.stats.unclaimed + len
+            > self.limits.max_bytes
+        {
+            match self.replayed.pop_front() {
+                Some((size, _)) => {
+                    self.stats.unclaimed -= size;
+                    self.stats.dropped += 1;
+                }
+                None => return false,
+            }
+        }
+        true
+    }
+
+    /// Records of the flowsets decoded since the last call
+    pub(crate) fn take_replayed(&mut self) -> Vec<OwnedFlowRecord> {
+        self.stats.unclaimed = 0;
+        self.replayed
+            .drain(..)
+            .flat_map(|(_, records)| records)
+            .collect()
+    }
+
+    /// Decode the flowsets waiting for `template`, to be taken
+    /// with `take_replayed`
+    pub(crate) fn replay(&mut self, key: &TemplateKey, template: &Template) {
+        let flowsets = match self.flowsets.remove(key) {
+            Some(flowsets) => flowsets,
+            None => return,
+        };
+        for pending in flowsets {
+            self.stats.pending -= 1;
+            self.stats.bytes -= pending.data.len();
+            if template.kind != TemplateKind::Data {
+                self.stats.dropped += 1;
+                continue;
+            }
+            let sets =
+                match parse_dataset(&pending.data, pending.tl_header, template)
+                {
+                    Ok((_, sets)) => sets,
+                    Err(_) => {
+                        self.stats.dropped += 1;
+                        continue;
+                    }
+                };
+            self.stats.replayed += 1;
+            let records = sets
+                .into_iter()
+                .map(|mut set| {
+                    set.set_source_ip(&pending.exporter);
+                    set.set_export_header(pending.header);
+                    set.into_owned()
+                })
+                .collect();
+            // The flowset no longer counts as waiting, so it fits
+            self.stats.unclaimed += pending.data.len();
+            self.replayed.push_back((pending.data.len(), records));
+        }
+    }
+
+    /// Drop the flowsets of `exporter` and `source_id`, their
+    /// templates will not arrive anymore
+    pub(crate) fn discard(&mut self, exporter: IpAddr, source_id: u32) {
+        let stats = &mut self.stats;
+        self.flowsets.retain(|key, flowsets| {
+            if key.exporter != exporter || key.source_id != source_id {
+                return true;
+            }
+            for pending in flowsets.drain(..) {
+                stats.pending -= 1;
+                stats.bytes -= pending.data.len();
+                stats.dropped += 1;
+            }
+            false
+        });
+    }
+
+    /// Drop the flowsets older than the maximum age. Only looks
+    /// for them once a second at most.
+    pub(crate) fn expire(&mut self, now: Instant) {
+        let interval = self.limits.max_age.min(Duration::from_secs(1));
+        if now.duration_since(self.expired) < interval {
+            return;
+        }
+        self.expired = now;
+        let max_age = self.limits.max_age;
+        let stats = &mut self.stats;
+        self.flowsets.retain(|_, flowsets| {
+            while let Some(pending) = flowsets.front() {
+                if now.duration_since(pending.received) < max_age {
+                    break;
+                }
+                stats.pending -= 1;
+                stats.bytes -= pending.data.len();
+                stats.expired += 1;
+                flowsets.pop_front();
+            }
+            !flowsets.is_empty()
+        });
+    }
+}
@@ -0,0 +1,285 @@
+use crate::formaters::FormatOptions;
+use crate::{DataFlowset, NetflowHeader, TypeLenHeader};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+/// An owned copy of a data record that can be serialized and
+/// read back without loss. Field values are kept as the raw
+/// bytes found in the packet, keyed by field ID, along with the
+/// export header values needed to decode them. The values of
+/// all the fields share a single allocation, so the record is
+/// cheap to hand over to another thread once the receive
+/// buffer gets reused.
+///
+/// Human readable formats (JSON) store the values as hex
+/// strings and binary formats (MessagePack, CBOR) as byte
+/// strings.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OwnedFlowRecord {
+    source_ip: Option<IpAddr>,
+    flowset_id: u16,
+    flowset_length: u16,
+    sys_uptime: u32,
+    export_time: u32,
+    sequence: u32,
+    source_id: u32,
+    fields: RawFields,
+}
+
+/// Field values laid out back to back in `data`, with the
+/// field IDs and end offsets in `index`, sorted by field ID
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct RawFields {
+    index: Box<[(u16, u16)]>,
+    data: Box<[u8]>,
+}
+
+impl RawFields {
+    /// Build from (field, value) pairs sorted by field ID
+    fn new(fields: &[(u16, &[u8])]) -> Self {
+        let mut index = Vec::with_capacity(fields.len());
+        let mut data =
+            Vec::with_capacity(fields.iter().map(|(_, v)| v.len()).sum());
+        for (f, v) in fields {
+            data.extend_from_slice(v);
+            index.push((*f, data.len() as u16));
+        }
+        RawFields {
+            index: index.into_boxed_slice(),
+            data: data.into_boxed_slice(),
+        }
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (u16, &[u8])> {
+        let starts = std::iter::once(0).chain(self.index.iter().map(|e| e.1));
+        self.index
+            .iter()
+            .zip(starts)
+            .map(move |(&(f, end), start)| {
+                (f, &self.data[start as usize..end as usize])
+            })
+    }
+}
+
+impl OwnedFlowRecord {
+    /// Address of the exporter that sent the record
+    pub fn source_ip(&self) -> Option<IpAddr> {
+        self.source_ip
+    }
+
+    /// ID of the template the record was exported with
+    pub fn flowset_id(&self) -> u16 {
+        self.flowset_id
+    }
+
+    /// Sequence number of the export packet
+    pub fn sequence(&self) -> u32 {
+        self.sequence
+    }
+
+    /// Source ID (observation domain) of the export packet
+    pub fn source_id(&self) -> u32 {
+        self.source_id
+    }
+
+    /// Exporter uptime in milliseconds when the packet was sent
+    pub fn sys_uptime(&self) -> u32 {
+        self.sys_uptime
+    }
+
+    /// Export time of the packet in unix seconds
+    pub fn export_time(&self) -> u32 {
+        self.export_time
+    }
+
+    /// Raw value of a field
+    pub fn get(&self, field: u16) -> Option<&[u8]> {
+        let index = &self.fields.index;
+        let i = index.binary_search_by_key(&field, |e| e.0).ok()?;
+        let start = if i == 0 { 0 } else { index[i - 1].1 };
+        Some(&self.fields.data[start as usize..index[i].1 as usize])
+    }
+
+    /// Field IDs and raw values, in field ID order
+    pub fn fields(&self) -> impl Iterator<Item = (u16, &[u8])> {
+        self.fields.iter()
+    }
+
+    /// Borrow the record as a `DataFlowset` so that it can go
+    /// through the same output and aggregation code as freshly
+    /// parsed flows
+    pub fn flowset(&self) -> DataFlowset<'_> {
+        DataFlowset {
+            source_ip: self.source_ip.as_ref(),
+            tl_header: TypeLenHeader {
+                flowset_id: self.flowset_id,
+                length: self.flowset_length,
+            },
+            records: self.fields().collect::<HashMap<_, _>>(),
+            options: FormatOptions::default(),
+            export_header: NetflowHeader {
+                version: 9,
+                count: 0,
+                sys_uptime: self.sys_uptime,
+                timestamp: self.export_time,
+                sequence: self.sequence,
+                source_id: self.source_id,
+            },
+        }
+    }
+}
+
+impl<'r, 'a> From<&'r DataFlowset<'a>> for OwnedFlowRecord {
+    fn from(set: &'r DataFlowset<'a>) -> Self {
+        let mut fields: Vec<(u16, &[u8])> =
+            set.records.iter().map(|(f, v)| (*f, *v)).collect();
+        fields.sort_unstable_by_key(|(f, _)| *f);
+        OwnedFlowRecord {
+            source_ip: set.source_ip.copied(),
+            flowset_id: set.tl_header.flowset_id,
+            flowset_length: set.tl_header.length,
+            sys_uptime: set.export_header.sys_uptime,
+            export_time: set.export_header.timestamp,
+            sequence: set.export_header.sequence,
+            source_id: set.export_header.source_id,
+            fields: RawFields::new(&fields),
+        }
+    }
+}
+
+/// Field map with raw values as hex strings in human readable
+/// formats and as byte strings otherwise
+mod raw_fields {
+    use super::RawFields;
+    use serde::de::{self, MapAccess, SeqAccess, Visitor};
+    use serde::ser::SerializeMap;
+    use serde::{Deserializer, Serializer};
+    use std::fmt;
+
+    struct Raw<'v>(&'v [u8]);
+
+    impl<'v> serde::Serialize for Raw<'v> {
+        fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            if serializer.is_human_readable() {
+                let hex: String =
+                    self.0.iter().map(|b| format!("{:02x}", b)).collect();
+                serializer.serialize_str(&hex)
+            } else {
+                serializer.serialize_bytes(self.0)
+            }
+        }
+    }
+
+    struct RawBuf(Vec<u8>);
+
+    impl<'de> serde::Deserialize<'de> for RawBuf {
+        fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            if deserializer.is_human_readable() {
+                deserializer.deserialize_str(RawVisitor)
+            } else {
+                deserializer.deserialize_byte_buf(RawVisitor)
+            }
+        }
+    }
+
+    struct RawVisitor;
+
+    impl<'de> Visitor<'de> for RawVisitor {
+        type Value = RawBuf;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a hex string or a byte string")
+        }
+
+        fn visit_str<E: de::Error>(self, v: &str) -> Result<RawBuf, E> {
+            if !v.is_ascii() || v.len() % 2 != 0 {
+                return Err(E::invalid_value(de::Unexpected::Str(v), &self));
+            }
+            (0..v.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&v[i..i + 2], 16))
+                .collect::<Result<Vec<u8>, _>>()
+                .map(RawBuf)
+                .map_err(|_| E::invalid_value(de::Unexpected::Str(v), &self))
+        }
+
+        fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<RawBuf, E> {
+            Ok(RawBuf(v.to_vec()))
+        }
+
+        fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<RawBuf, E> {
+            Ok(RawBuf(v))
+        }
+
+        // Some formats hand byte strings over as sequences
+        fn visit_seq<A>(self, mut seq: A) -> Result<RawBuf, A::Error>
+        where
+            A: SeqAccess<'de>,
+        {
+            let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+            while let Some(b) = seq.next_element()? {
+                bytes.push(b);
+            }
+            Ok(RawBuf(bytes))
+        }
+    }
+
+    impl serde::Serialize for RawFields {
+        fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            let mut map = serializer.serialize_map(Some(self.index.len()))?;
+            for (f, v) in self.iter() {
+                map.serialize_entry(&f, &Raw(v))?;
+            }
+            map.end()
+        }
+    }
+
+    struct FieldsVisitor;
+
+    impl<'de> Visitor<'de> for FieldsVisitor {
+        type Value = RawFields;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a map of field IDs to raw values")
+        }
+
+        fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+        where
+            A: MapAccess<'de>,
+        {
+            let mut fields = Vec::with_capacity(map.size_hint().unwrap_or(0));
+            while let Some((f, RawBuf(v))) = map.next_entry::<u16, RawBuf>()? {
+                fields.push((f, v));
+            }
+            fields.sort_unstable_by_key(|(f, _)| *f);
+            fields.dedup_by_key(|(f, _)| *f);
+            // Offsets are 16 bit, as is the length of a flowset
+            let len: usize = fields.iter().map(|(_, v)| v.len()).sum();
+            if len > usize::from(u16::MAX) {
+                return Err(de::Error::custom("record values too long"));
+            }
+            let fields: Vec<(u16, &[u8])> =
+                fields.iter().map(|(f, v)| (*f, &v[..])).collect();
+            Ok(RawFields::new(&fields))
+        }
+    }
+
+    impl<'de> serde::Deserialize<'de> for RawFields {
+        fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            deserializer.deserialize_map(FieldsVisitor)
+        }
+    }
+}
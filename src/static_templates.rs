@@ -0,0 +1,108 @@
+use crate::template_store::{Template, TemplateKey, TemplateKind};
+use crate::templates::field_from_name;
+use crate::TemplateField;
+use serde::de::{self, Deserializer};
+use serde::Deserialize;
+use std::net::IpAddr;
+use std::time::SystemTime;
+
+/// What the parser does with a received template that has a
+/// static definition
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StaticTemplatePolicy {
+    /// Use the received template from now on
+    #[default]
+    Override,
+    /// Keep the static definition, only check that the received
+    /// template agrees with it
+    Verify,
+}
+
+/// A template defined ahead of time for exporters that rarely
+/// send theirs, so that their records are decoded right away.
+/// Deserializes from entries such as, in TOML:
+///
+/// ```toml
+/// exporter = "192.0.2.1"
+/// source_id = 0
+/// template_id = 256
+/// fields = [
+///     { field = "sourceIPv4Address", len = 4 },
+///     { field = "l4_src_port", len = 2 },
+///     { field = 1, len = 4 },
+/// ]
+/// ```
+///
+/// Fields are given by label, IANA, snake_case or nfdump name,
+/// or by ID. Options templates also take `scope_fields`, the
+/// number of scope fields at the start of `fields`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StaticTemplate {
+    pub exporter: IpAddr,
+    #[serde(default)]
+    pub source_id: u32,
+    pub template_id: u16,
+    #[serde(default)]
+    pub scope_fields: Option<u16>,
+    #[serde(deserialize_with = "deserialize_fields")]
+    pub fields: Vec<TemplateField>,
+}
+
+impl StaticTemplate {
+    pub fn key(&self) -> TemplateKey {
+        TemplateKey {
+            exporter: self.exporter,
+            source_id: self.source_id,
+            template_id: self.template_id,
+        }
+    }
+
+    pub fn template(&self) -> Template {
+        Template {
+            kind: match self.scope_fields {
+                Some(scope_fields) => TemplateKind::Options { scope_fields },
+                None => TemplateKind::Data,
+            },
+            fields: self.fields.clone(),
+            updated: SystemTime::now(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum FieldName {
+    Id(u16),
+    Name(String),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StaticField {
+    field: FieldName,
+    len: u16,
+}
+
+fn deserialize_fields<'de, D>(
+    deserializer: D,
+) -> Result<Vec<TemplateField>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<StaticField>::deserialize(deserializer)?
+        .into_iter()
+        .map(|f| {
+            let field = match f.field {
+                FieldName::Id(id) => id,
+                FieldName::Name(name) => {
+                    field_from_name(&name).ok_or_else(|| {
+                        de::Error::custom(format!("unknown field {}", name))
+                    })?
+                }
+            };
+            Ok(TemplateField { field, len: f.len })
+        })
+        .collect()
+}
@@ -0,0 +1,243 @@
+use crate::record::OwnedFlowRecord;
+use crate::{Parser, TemplateStore};
+use futures_core::Stream;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime};
+use std::{fmt, io};
+use tokio::net::{ToSocketAddrs, UdpSocket};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// What to do when the consumer of a `FlowStream` falls behind
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backpressure {
+    /// Stop reading the socket until there is room again. The
+    /// kernel drops datagrams once its buffer is full.
+    #[default]
+    Block,
+    /// Keep reading and drop the records that don't fit,
+    /// counting them in `FlowStream::dropped`
+    Drop,
+}
+
+#[derive(Debug, Clone)]
+pub struct StreamConfig {
+    /// Number of records buffered between the socket and the
+    /// consumer
+    pub capacity: usize,
+    pub backpressure: Backpressure,
+    /// Size of the receive buffer, the largest datagram accepted
+    pub buffer_size: usize,
+}
+
+impl Default for StreamConfig {
+    fn default() -> Self {
+        StreamConfig {
+            capacity: 1024,
+            backpressure: Backpressure::Block,
+            buffer_size: 65535,
+        }
+    }
+}
+
+/// A record along with where and when it was received
+#[derive(Debug, Clone)]
+pub struct ReceivedRecord {
+    /// Address and port the export packet came from
+    pub exporter: SocketAddr,
+    pub received: SystemTime,
+    pub record: OwnedFlowRecord,
+}
+
+#[derive(Debug)]
+pub enum ParseError {
+    /// Receiving from the socket failed
+    Io(io::Error),
+    /// The export packet could not be parsed
+    Packet {
+        exporter: SocketAddr,
+        reason: &'static str,
+    },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::Io(e) => write!(f, "{}", e),
+            ParseError::Packet { exporter, reason } => {
+                write!(f, "{}: {}", exporter, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+type Item = Result<ReceivedRecord, ParseError>;
+
+#[derive(Debug, Default)]
+struct Counters {
+    packets: AtomicU64,
+    dropped: AtomicU64,
+}
+
+/// Stream of the records received on a tokio `UdpSocket`.
+/// Packets are read and parsed on a task of their own.
+/// Dropping the stream stops the task.
+pub struct FlowStream {
+    receiver: mpsc::Receiver<Item>,
+    counters: Arc<Counters>,
+    local_addr: SocketAddr,
+    task: JoinHandle<()>,
+}
+
+impl FlowStream {
+    /// Start receiving on `socket`. Must be called from within
+    /// a tokio runtime.
+    pub fn new(socket: UdpSocket, config: StreamConfig) -> io::Result<Self> {
+        FlowStream::with_parser(socket, config, Parser::new())
+    }
+
+    /// Start receiving on `socket` with a configured `parser`,
+    /// for instance with static templates or pending flowsets.
+    /// Records decoded once their template arrives are streamed
+    /// along with those of the packet that brought it.
+    pub fn with_parser<S>(
+        socket: UdpSocket,
+        config: StreamConfig,
+        parser: Parser<S>,
+    ) -> io::Result<Self>
+    where
+        S: TemplateStore + Send + 'static,
+    {
+        let local_addr = socket.local_addr()?;
+        let (sender, receiver) = mpsc::channel(config.capacity.max(1));
+        let counters = Arc::new(Counters::default());
+        let task = tokio::spawn(receive(
+            socket,
+            config,
+            parser,
+            sender,
+            counters.clone(),
+        ));
+        Ok(FlowStream {
+            receiver,
+            counters,
+            local_addr,
+            task,
+        })
+    }
+
+    pub async fn bind<A: ToSocketAddrs>(
+        address: A,
+        config: StreamConfig,
+    ) -> io::Result<Self> {
+        let socket = UdpSocket::bind(address).await?;
+        FlowStream::new(socket, config)
+    }
+
+    /// Address the stream receives on
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Number of export packets received
+    pub fn packets(&self) -> u64 {
+        self.counters.packets.load(Ordering::Relaxed)
+    }
+
+    /// Number of records and errors dropped because the stream
+    /// was full, with `Backpressure::Drop`
+    pub fn dropped(&self) -> u64 {
+        self.counters.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl Stream for FlowStream {
+    type Item = Item;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+impl Drop for FlowStream {
+    fn drop(&mut self) {
+        self.task.abort()
+    }
+}
+
+async fn receive<S: TemplateStore>(
+    socket: UdpSocket,
+    config: StreamConfig,
+    mut parser: Parser<S>,
+    sender: mpsc::Sender<Item>,
+    counters: Arc<Counters>,
+) {
+    let mut buffer = vec![0; config.buffer_size];
+    loop {
+        let mut items = Vec::new();
+        match socket.recv_from(&mut buffer).await {
+            Ok((len, exporter)) => {
+                counters.packets.fetch_add(1, Ordering::Relaxed);
+                let received = SystemTime::now();
+                let ip = exporter.ip().to_canonical();
+                let records = match parser
+                    .parse_netflow_packet(&buffer[..len], &ip)
+                {
+                    Ok(sets) => {
+                        sets.into_iter().map(|set| set.into_owned()).collect()
+                    }
+                    Err(reason) => {
+                        items
+                            .push(Err(ParseError::Packet { exporter, reason }));
+                        Vec::new()
+                    }
+                };
+                // Records that waited for their template come first
+                items.extend(
+                    parser.take_replayed().into_iter().chain(records).map(
+                        |record| {
+                            Ok(ReceivedRecord {
+                                exporter,
+                                received,
+                                record,
+                            })
+                        },
+                    ),
+                );
+            }
+            Err(e) => items.push(Err(ParseError::Io(e))),
+        }
+        let failed = matches!(items.last(), Some(Err(ParseError::Io(_))));
+
+        for item in items {
+            match config.backpressure {
+                Backpressure::Block => {
+                    if sender.send(item).await.is_err() {
+                        return;
+                    }
+                }
+                Backpressure::Drop => match sender.try_send(item) {
+                    Ok(()) => {}
+                    Err(mpsc::error::TrySendError::Full(_)) => {
+                        counters.dropped.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Err(mpsc::error::TrySendError::Closed(_)) => return,
+                },
+            }
+        }
+        if failed {
+            // Such as an ICMP error queued on the socket, keep
+            // receiving without spinning on a lasting one
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+}
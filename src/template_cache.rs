@@ -0,0 +1,125 @@
+use crate::template_limits::{TemplateLimits, TemplateMap, TemplateStats};
+use crate::template_store::{Template, TemplateKey, TemplateStore};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// Bumped whenever a template is changed or removed
+    generation: AtomicU64,
+    templates: RwLock<TemplateMap>,
+}
+
+/// Template store shared by several `Parser`s, see
+/// `Parser::with_shared_cache`. Templates learned by any of the
+/// parsers are available to all of them.
+///
+/// Each handle keeps a local copy of the templates it looked
+/// up and only goes to the shared map when a template is
+/// missing or after another handle changed one, so the hot
+/// path takes no lock. Cloning gives another handle to the
+/// same cache.
+#[derive(Debug, Clone, Default)]
+pub struct SharedTemplateCache {
+    inner: Arc<Inner>,
+    local: HashMap<TemplateKey, Arc<Template>>,
+    /// Generation of the shared map `local` reflects
+    generation: u64,
+}
+
+impl SharedTemplateCache {
+    pub fn new() -> Self {
+        SharedTemplateCache::default()
+    }
+
+    /// A cache bounded by `limits`, which apply to the templates
+    /// of all the handles together. Templates are marked as used
+    /// when received and when a handle fetches them from the
+    /// shared map, not on lookups served by its local copy.
+    pub fn with_limits(limits: TemplateLimits) -> Self {
+        SharedTemplateCache {
+            inner: Arc::new(Inner {
+                generation: AtomicU64::new(0),
+                templates: RwLock::new(TemplateMap::new(limits)),
+            }),
+            local: HashMap::new(),
+            generation: 0,
+        }
+    }
+
+    pub fn stats(&self) -> TemplateStats {
+        self.inner.templates.read().unwrap().stats()
+    }
+
+    fn changed(&self) {
+        self.inner.generation.fetch_add(1, Ordering::Release);
+    }
+}
+
+impl TemplateStore for SharedTemplateCache {
+    /// Exporters resend their templates periodically, the other
+    /// handles only drop their local copies when a layout
+    /// changes or templates are evicted. New templates are found
+    /// in the shared map when missing from the local copy.
+    fn insert(&mut self, key: TemplateKey, template: Arc<Template>) {
+        let mut templates = self.inner.templates.write().unwrap();
+        let redefined = templates
+            .get(&key)
+            .is_some_and(|t| !t.same_layout(&template));
+        let evicted = templates.insert(key, template.clone());
+        drop(templates);
+        if redefined || evicted.is_some_and(|n| n > 0) {
+            self.changed();
+        }
+        if evicted.is_some() {
+            self.local.insert(key, template);
+        }
+    }
+
+    fn lookup(&mut self, key: &TemplateKey) -> Option<Arc<Template>> {
+        let generation = self.inner.generation.load(Ordering::Acquire);
+        if generation != self.generation {
+            self.local.clear();
+            self.generation = generation;
+        }
+        if let Some(template) = self.local.get(key) {
+            return Some(template.clone());
+        }
+        let template = self.inner.templates.read().unwrap().touch(key)?;
+        self.local.insert(*key, template.clone());
+        Some(template)
+    }
+
+    fn withdraw(&mut self, key: &TemplateKey) -> Option<Arc<Template>> {
+        let template = self.inner.templates.write().unwrap().remove(key)?;
+        self.changed();
+        Some(template)
+    }
+
+    /// A snapshot of the shared templates
+    fn iter(
+        &self,
+    ) -> Box<dyn Iterator<Item = (TemplateKey, Arc<Template>)> + '_> {
+        let templates = self.inner.templates.read().unwrap();
+        let snapshot: Vec<_> = templates.iter().collect();
+        Box::new(snapshot.into_iter())
+    }
+
+    fn expire(&mut self, before: SystemTime) -> usize {
+        let expired = self.inner.templates.write().unwrap().expire(before);
+        if expired > 0 {
+            self.changed();
+        }
+        expired
+    }
+
+    fn len(&self) -> usize {
+        self.inner.templates.read().unwrap().len()
+    }
+
+    fn limits(&self) -> TemplateLimits {
+        self.inner.templates.read().unwrap().limits()
+    }
+}
@@ -0,0 +1,266 @@
+use crate::template_store::{Template, TemplateKey};
+use crate::TemplateField;
+use std::collections::{BTreeMap, HashMap};
+use std::mem;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// Bounds on the templates a store keeps, see
+/// `MemoryTemplateStore::with_limits` and
+/// `SharedTemplateCache::with_limits`. `None` is unlimited.
+///
+/// Beyond the template and byte limits the least recently used
+/// templates are evicted, first among those of the exporter
+/// then among all. Beyond `max_exporters` the templates of new
+/// exporters are rejected, so that spoofed sources can't push
+/// out the templates of known ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TemplateLimits {
+    pub max_templates: Option<usize>,
+    /// Approximate memory used by the templates, see
+    /// `Template::size`
+    pub max_bytes: Option<usize>,
+    pub max_templates_per_exporter: Option<usize>,
+    pub max_bytes_per_exporter: Option<usize>,
+    pub max_exporters: Option<usize>,
+}
+
+/// Counters of a template store
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TemplateStats {
+    pub templates: usize,
+    pub bytes: usize,
+    pub exporters: usize,
+    /// Templates removed to make room for others
+    pub evicted: u64,
+    /// Templates not stored because of the limits, from new
+    /// exporters or larger than a byte limit
+    pub rejected: u64,
+}
+
+impl Template {
+    /// Approximate memory used by the template, as counted by
+    /// `TemplateLimits`
+    pub fn size(&self) -> usize {
+        mem::size_of::<Template>()
+            + self.fields.len() * mem::size_of::<TemplateField>()
+    }
+}
+
+/// A counter that can be bumped through a shared reference,
+/// so that lookups only need a read lock on the map
+#[derive(Debug, Default)]
+struct Tick(AtomicU64);
+
+impl Tick {
+    fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    fn next(&self) -> u64 {
+        self.0.fetch_add(1, Ordering::Relaxed) + 1
+    }
+}
+
+impl Clone for Tick {
+    fn clone(&self) -> Self {
+        Tick(AtomicU64::new(self.get()))
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    template: Arc<Template>,
+    size: usize,
+    /// Key of the entry in the LRU indexes
+    indexed: u64,
+    /// Tick of the last use. Lookups only update this, the
+    /// indexes catch up when looking for a template to evict.
+    used: Tick,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Exporter {
+    bytes: usize,
+    lru: BTreeMap<u64, TemplateKey>,
+}
+
+/// Templates by key along with their memory use per exporter
+/// and the order they were used in
+#[derive(Debug, Clone, Default)]
+pub(crate) struct TemplateMap {
+    limits: TemplateLimits,
+    templates: HashMap<TemplateKey, Entry>,
+    exporters: HashMap<IpAddr, Exporter>,
+    lru: BTreeMap<u64, TemplateKey>,
+    bytes: usize,
+    tick: Tick,
+    evicted: u64,
+    rejected: u64,
+}
+
+fn over(value: usize, limit: Option<usize>) -> bool {
+    limit.is_some_and(|limit| value > limit)
+}
+
+impl TemplateMap {
+    pub(crate) fn new(limits: TemplateLimits) -> Self {
+        TemplateMap {
+            limits,
+            ..TemplateMap::default()
+        }
+    }
+
+    pub(crate) fn limits(&self) -> TemplateLimits {
+        self.limits
+    }
+
+    pub(crate) fn stats(&self) -> TemplateStats {
+        TemplateStats {
+            templates: self.templates.len(),
+            bytes: self.bytes,
+            exporters: self.exporters.len(),
+            evicted: self.evicted,
+            rejected: self.rejected,
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.templates.len()
+    }
+
+    pub(crate) fn get(&self, key: &TemplateKey) -> Option<&Arc<Template>> {
+        self.templates.get(key).map(|e| &e.template)
+    }
+
+    /// Look a template up and mark it as used
+    pub(crate) fn touch(&self, key: &TemplateKey) -> Option<Arc<Template>> {
+        let entry = self.templates.get(key)?;
+        entry.used.0.store(self.tick.next(), Ordering::Relaxed);
+        Some(entry.template.clone())
+    }
+
+    /// The least recently used template, of `exporter` or of
+    /// all. Entries used since they were indexed are moved to
+    /// their place on the way.
+    fn oldest(&mut self, exporter: Option<IpAddr>) -> Option<TemplateKey> {
+        loop {
+            let lru = match exporter {
+                Some(exporter) => &self.exporters.get(&exporter)?.lru,
+                None => &self.lru,
+            };
+            let (&indexed, &key) = lru.iter().next()?;
+            let entry = self.templates.get_mut(&key).unwrap();
+            let used = entry.used.get();
+            if used <= indexed {
+                return Some(key);
+            }
+            entry.indexed = used;
+            let exporter = self.exporters.get_mut(&key.exporter).unwrap();
+            exporter.lru.remove(&indexed);
+            exporter.lru.insert(used, key);
+            self.lru.remove(&indexed);
+            self.lru.insert(used, key);
+        }
+    }
+
+    pub(crate) fn iter(
+        &self,
+    ) -> impl Iterator<Item = (TemplateKey, Arc<Template>)> + '_ {
+        self.templates.iter().map(|(k, e)| (*k, e.template.clone()))
+    }
+
+    /// Add or replace a template, evicting others as needed.
+    /// Returns the number of templates evicted, `None` if the
+    /// template was rejected.
+    pub(crate) fn insert(
+        &mut self,
+        key: TemplateKey,
+        template: Arc<Template>,
+    ) -> Option<usize> {
+        let size = template.size();
+        let new_exporter = !self.exporters.contains_key(&key.exporter);
+        if (new_exporter
+            && self
+                .limits
+                .max_exporters
+                .is_some_and(|max| self.exporters.len() >= max))
+            || over(size, self.limits.max_bytes)
+            || over(size, self.limits.max_bytes_per_exporter)
+        {
+            self.rejected += 1;
+            return None;
+        }
+
+        self.remove(&key);
+        let tick = self.tick.next();
+        let exporter = self.exporters.entry(key.exporter).or_default();
+        exporter.bytes += size;
+        exporter.lru.insert(tick, key);
+        self.lru.insert(tick, key);
+        self.bytes += size;
+        self.templates.insert(
+            key,
+            Entry {
+                template,
+                size,
+                indexed: tick,
+                used: Tick(AtomicU64::new(tick)),
+            },
+        );
+
+        let mut evicted = 0;
+        while let Some(exporter) = self.exporters.get(&key.exporter) {
+            if !over(exporter.lru.len(), self.limits.max_templates_per_exporter)
+                && !over(exporter.bytes, self.limits.max_bytes_per_exporter)
+            {
+                break;
+            }
+            let oldest = self.oldest(Some(key.exporter)).unwrap();
+            self.remove(&oldest);
+            evicted += 1;
+        }
+        while over(self.templates.len(), self.limits.max_templates)
+            || over(self.bytes, self.limits.max_bytes)
+        {
+            let oldest = self.oldest(None).unwrap();
+            self.remove(&oldest);
+            evicted += 1;
+        }
+        self.evicted += evicted as u64;
+        Some(evicted)
+    }
+
+    pub(crate) fn remove(
+        &mut self,
+        key: &TemplateKey,
+    ) -> Option<Arc<Template>> {
+        let entry = self.templates.remove(key)?;
+        self.lru.remove(&entry.indexed);
+        self.bytes -= entry.size;
+        let exporter = self.exporters.get_mut(&key.exporter).unwrap();
+        exporter.lru.remove(&entry.indexed);
+        exporter.bytes -= entry.size;
+        if exporter.lru.is_empty() {
+            self.exporters.remove(&key.exporter);
+        }
+        Some(entry.template)
+    }
+
+    /// Remove the templates last received before `before`,
+    /// returning how many were removed
+    pub(crate) fn expire(&mut self, before: SystemTime) -> usize {
+        let expired: Vec<TemplateKey> = self
+            .templates
+            .iter()
+            .filter(|(_, e)| e.template.updated < before)
+            .map(|(k, _)| *k)
+            .collect();
+        for key in &expired {
+            self.remove(key);
+        }
+        expired.len()
+    }
+}
@@ -0,0 +1,181 @@
+use crate::template_store::{
+    Template, TemplateKey, TemplateKind, TemplateStore,
+};
+use crate::TemplateField;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Version of the snapshot format, bumped on incompatible
+/// changes
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum SavedKind {
+    Data,
+    Options { scope_fields: u16 },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct SavedTemplate {
+    exporter: IpAddr,
+    source_id: u32,
+    template_id: u16,
+    kind: SavedKind,
+    /// Field ID and length pairs
+    fields: Vec<(u16, u16)>,
+    /// When the template was last received, unix milliseconds
+    updated: u64,
+}
+
+/// The content of a `TemplateStore` at some point in time, to
+/// be saved when a collector stops and restored when it starts
+/// so that it does not have to wait for the exporters to send
+/// their templates again. Stored as JSON.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TemplateSnapshot {
+    version: u32,
+    /// When the snapshot was taken, unix milliseconds
+    taken: u64,
+    templates: Vec<SavedTemplate>,
+}
+
+fn to_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+fn from_millis(ms: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(ms)
+}
+
+impl TemplateSnapshot {
+    pub fn capture<S: TemplateStore + ?Sized>(store: &S) -> Self {
+        let mut templates: Vec<SavedTemplate> = store
+            .iter()
+            .map(|(key, template)| SavedTemplate {
+                exporter: key.exporter,
+                source_id: key.source_id,
+                template_id: key.template_id,
+                kind: match template.kind {
+                    TemplateKind::Data => SavedKind::Data,
+                    TemplateKind::Options { scope_fields } => {
+                        SavedKind::Options { scope_fields }
+                    }
+                },
+                fields: template
+                    .fields
+                    .iter()
+                    .map(|f| (f.field, f.len))
+                    .collect(),
+                updated: to_millis(template.updated),
+            })
+            .collect();
+        templates.sort_by_key(|t| (t.exporter, t.source_id, t.template_id));
+        TemplateSnapshot {
+            version: SNAPSHOT_VERSION,
+            taken: to_millis(SystemTime::now()),
+            templates,
+        }
+    }
+
+    /// When the snapshot was taken
+    pub fn taken(&self) -> SystemTime {
+        from_millis(self.taken)
+    }
+
+    pub fn len(&self) -> usize {
+        self.templates.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.templates.is_empty()
+    }
+
+    /// Add the templates of the snapshot to `store`, skipping
+    /// those last received more than `max_age` ago and those
+    /// the store has a more recent copy of. Returns the number
+    /// of templates restored.
+    pub fn restore<S: TemplateStore + ?Sized>(
+        &self,
+        store: &mut S,
+        max_age: Option<Duration>,
+    ) -> usize {
+        let oldest = max_age.and_then(|age| SystemTime::now().checked_sub(age));
+        let mut restored = 0;
+        for saved in &self.templates {
+            let updated = from_millis(saved.updated);
+            if oldest.is_some_and(|oldest| updated < oldest) {
+                continue;
+            }
+            let key = TemplateKey {
+                exporter: saved.exporter,
+                source_id: saved.source_id,
+                template_id: saved.template_id,
+            };
+            if store.lookup(&key).is_some_and(|t| t.updated >= updated) {
+                continue;
+            }
+            let template = Template {
+                kind: match saved.kind {
+                    SavedKind::Data => TemplateKind::Data,
+                    SavedKind::Options { scope_fields } => {
+                        TemplateKind::Options { scope_fields }
+                    }
+                },
+                fields: saved
+                    .fields
+                    .iter()
+                    .map(|&(field, len)| TemplateField { field, len })
+                    .collect(),
+                updated,
+            };
+            store.insert(key, Arc::new(template));
+            restored += 1;
+        }
+        restored
+    }
+
+    pub fn write<W: io::Write>(&self, writer: W) -> io::Result<()> {
+        serde_json::to_writer_pretty(writer, self)?;
+        Ok(())
+    }
+
+    /// Read a snapshot, failing on snapshots of another version
+    pub fn read<R: io::Read>(reader: R) -> io::Result<Self> {
+        let snapshot: TemplateSnapshot = serde_json::from_reader(reader)?;
+        if snapshot.version != SNAPSHOT_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "unsupported template snapshot version {}",
+                    snapshot.version
+                ),
+            ));
+        }
+        Ok(snapshot)
+    }
+
+    /// Write the snapshot to `path`. The file is replaced
+    /// atomically so that a crash never leaves half a snapshot.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        let mut writer = BufWriter::new(File::create(&temporary)?);
+        self.write(&mut writer)?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        fs::rename(&temporary, path)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        TemplateSnapshot::read(BufReader::new(File::open(path)?))
+    }
+}
@@ -0,0 +1,170 @@
+use crate::template_limits::{TemplateLimits, TemplateMap, TemplateStats};
+use crate::TemplateField;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// Templates are scoped by exporter and source ID, the same
+/// template ID can mean different things on two exporters
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TemplateKey {
+    pub exporter: IpAddr,
+    pub source_id: u32,
+    pub template_id: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TemplateKind {
+    Data,
+    /// Options template, the first `scope_fields` fields are
+    /// scope fields
+    Options {
+        scope_fields: u16,
+    },
+}
+
+/// A template as kept by a `TemplateStore`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    pub kind: TemplateKind,
+    /// Fields in record order
+    pub fields: Vec<TemplateField>,
+    /// When the template was last received
+    pub updated: SystemTime,
+}
+
+impl Template {
+    /// Whether both templates describe the same records,
+    /// regardless of when they were received
+    pub fn same_layout(&self, other: &Template) -> bool {
+        self.kind == other.kind && self.fields == other.fields
+    }
+
+    /// Length in bytes of a record
+    pub fn record_len(&self) -> usize {
+        self.fields.iter().map(|f| usize::from(f.len)).sum()
+    }
+}
+
+/// Where a `Parser` keeps its templates. Implement it to keep
+/// templates in a database, in shared memory or anywhere else.
+///
+/// `lookup` takes `&mut self` so that stores backed by slower
+/// storage can keep local copies.
+pub trait TemplateStore {
+    /// Add a template, replacing any template with the same key
+    fn insert(&mut self, key: TemplateKey, template: Arc<Template>);
+
+    fn lookup(&mut self, key: &TemplateKey) -> Option<Arc<Template>>;
+
+    /// Remove a template, returning it
+    fn withdraw(&mut self, key: &TemplateKey) -> Option<Arc<Template>>;
+
+    /// All the templates, in no particular order
+    fn iter(
+        &self,
+    ) -> Box<dyn Iterator<Item = (TemplateKey, Arc<Template>)> + '_>;
+
+    /// Remove the templates last received before `before`,
+    /// returning how many were removed
+    fn expire(&mut self, before: SystemTime) -> usize;
+
+    fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Bounds on the templates kept, none by default
+    fn limits(&self) -> TemplateLimits {
+        TemplateLimits::default()
+    }
+}
+
+impl<S: TemplateStore + ?Sized> TemplateStore for Box<S> {
+    fn insert(&mut self, key: TemplateKey, template: Arc<Template>) {
+        (**self).insert(key, template)
+    }
+
+    fn lookup(&mut self, key: &TemplateKey) -> Option<Arc<Template>> {
+        (**self).lookup(key)
+    }
+
+    fn withdraw(&mut self, key: &TemplateKey) -> Option<Arc<Template>> {
+        (**self).withdraw(key)
+    }
+
+    fn iter(
+        &self,
+    ) -> Box<dyn Iterator<Item = (TemplateKey, Arc<Template>)> + '_> {
+        (**self).iter()
+    }
+
+    fn expire(&mut self, before: SystemTime) -> usize {
+        (**self).expire(before)
+    }
+
+    fn len(&self) -> usize {
+        (**self).len()
+    }
+
+    fn limits(&self) -> TemplateLimits {
+        (**self).limits()
+    }
+}
+
+/// The default store, a map owned by the parser
+#[derive(Debug, Clone, Default)]
+pub struct MemoryTemplateStore {
+    templates: TemplateMap,
+}
+
+impl MemoryTemplateStore {
+    pub fn new() -> Self {
+        MemoryTemplateStore::default()
+    }
+
+    pub fn with_limits(limits: TemplateLimits) -> Self {
+        MemoryTemplateStore {
+            templates: TemplateMap::new(limits),
+        }
+    }
+
+    pub fn stats(&self) -> TemplateStats {
+        self.templates.stats()
+    }
+}
+
+impl TemplateStore for MemoryTemplateStore {
+    fn insert(&mut self, key: TemplateKey, template: Arc<Template>) {
+        self.templates.insert(key, template);
+    }
+
+    fn lookup(&mut self, key: &TemplateKey) -> Option<Arc<Template>> {
+        self.templates.touch(key)
+    }
+
+    fn withdraw(&mut self, key: &TemplateKey) -> Option<Arc<Template>> {
+        self.templates.remove(key)
+    }
+
+    fn iter(
+        &self,
+    ) -> Box<dyn Iterator<Item = (TemplateKey, Arc<Template>)> + '_> {
+        Box::new(self.templates.iter())
+    }
+
+    fn expire(&mut self, before: SystemTime) -> usize {
+        self.templates.expire(before)
+    }
+
+    fn len(&self) -> usize {
+        self.templates.len()
+    }
+
+    fn limits(&self) -> TemplateLimits {
+        self.templates.limits()
+    }
+}
@@ -1,5 +1,12 @@
-use super::formaters::{fmt_int, fmt_ipv4, fmt_ipv6, fmt_tcp_flags, FmtReturn};
+use super::formaters::{
+    fmt_direction, fmt_forwarding_status, fmt_icmp, fmt_int, fmt_ipv4,
+    fmt_ipv6, fmt_protocol, fmt_sampling_algorithm, fmt_tcp_flags, FmtReturn,
+    KeyStyle,
+};
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::fmt;
+use std::sync::OnceLock;
 
 pub enum TemplateFieldType {
     InBytes(&'static str, fn(&[u8]) -> FmtReturn),
@@ -84,7 +91,13 @@ pub enum TemplateFieldType {
 
 impl fmt::Display for TemplateFieldType {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let enum_value = match *self {
+        write!(f, "{}", self.label())
+    }
+}
+
+impl TemplateFieldType {
+    pub fn label(&self) -> &'static str {
+        match *self {
             TemplateFieldType::InBytes(x, _) => x,
             TemplateFieldType::InPkts(x, _) => x,
             TemplateFieldType::Flows(x, _) => x,
@@ -127,7 +140,23 @@ impl fmt::Display for TemplateFieldType {
             TemplateFieldType::TotalBytesExp(x, _) => x,
             TemplateFieldType::TotalPktsExp(x, _) => x,
             TemplateFieldType::TotalFlowsExp(x, _) => x,
-
+            TemplateFieldType::IPv4SrcPrefix(x, _) => x,
+            TemplateFieldType::IPv4DstPrefix(x, _) => x,
+            TemplateFieldType::MPLSTopLabelType(x, _) => x,
+            TemplateFieldType::MPLSTopLabelIPAddr(x, _) => x,
+            TemplateFieldType::FlowSamplerID(x, _) => x,
+            TemplateFieldType::FlowSamplerMode(x, _) => x,
+            TemplateFieldType::FlowSamplerRandomInterval(x, _) => x,
+            TemplateFieldType::MPLSLabel1(x, _) => x,
+            TemplateFieldType::MPLSLabel2(x, _) => x,
+            TemplateFieldType::MPLSLabel3(x, _) => x,
+            TemplateFieldType::MPLSLabel4(x, _) => x,
+            TemplateFieldType::MPLSLabel5(x, _) => x,
+            TemplateFieldType::MPLSLabel6(x, _) => x,
+            TemplateFieldType::MPLSLabel7(x, _) => x,
+            TemplateFieldType::MPLSLabel8(x, _) => x,
+            TemplateFieldType::MPLSLabel9(x, _) => x,
+            TemplateFieldType::MPLSLabel10(x, _) => x,
             TemplateFieldType::MinTTL(x, _) => x,
             TemplateFieldType::MaxTTL(x, _) => x,
             TemplateFieldType::IPv4Ident(x, _) => x,
@@ -150,15 +179,9 @@ impl fmt::Display for TemplateFieldType {
             TemplateFieldType::ReplicationFactor(x, _) => x,
 
             TemplateFieldType::Unimplemented(x, _) => x,
-
-            _ => "Unknown value",
-        };
-
-        write!(f, "{}", enum_value)
+        }
     }
-}
 
-impl TemplateFieldType {
     pub fn get_parser(&self) -> fn(&[u8]) -> FmtReturn {
         match *self {
             TemplateFieldType::InBytes(_, f) => f,
@@ -218,7 +241,23 @@ impl TemplateFieldType {
             TemplateFieldType::TotalBytesExp(_, f) => f,
             TemplateFieldType::TotalPktsExp(_, f) => f,
             TemplateFieldType::TotalFlowsExp(_, f) => f,
-
+            TemplateFieldType::IPv4SrcPrefix(_, f) => f,
+            TemplateFieldType::IPv4DstPrefix(_, f) => f,
+            TemplateFieldType::MPLSTopLabelType(_, f) => f,
+            TemplateFieldType::MPLSTopLabelIPAddr(_, f) => f,
+            TemplateFieldType::FlowSamplerID(_, f) => f,
+            TemplateFieldType::FlowSamplerMode(_, f) => f,
+            TemplateFieldType::FlowSamplerRandomInterval(_, f) => f,
+            TemplateFieldType::MPLSLabel1(_, f) => f,
+            TemplateFieldType::MPLSLabel2(_, f) => f,
+            TemplateFieldType::MPLSLabel3(_, f) => f,
+            TemplateFieldType::MPLSLabel4(_, f) => f,
+            TemplateFieldType::MPLSLabel5(_, f) => f,
+            TemplateFieldType::MPLSLabel6(_, f) => f,
+            TemplateFieldType::MPLSLabel7(_, f) => f,
+            TemplateFieldType::MPLSLabel8(_, f) => f,
+            TemplateFieldType::MPLSLabel9(_, f) => f,
+            TemplateFieldType::MPLSLabel10(_, f) => f,
             TemplateFieldType::IPv4Ident(_, f) => f,
             TemplateFieldType::InSrcMac(_, f) => f,
             TemplateFieldType::OutDstMac(_, f) => f,
@@ -231,22 +270,21 @@ impl TemplateFieldType {
             TemplateFieldType::IfDesc(_, f) => f,
 
             TemplateFieldType::Unimplemented(_, f) => f,
-
-            _ => {
-                println!("{}", *self);
-                |_: &[u8]| FmtReturn::Text("Unknown value".to_owned())
-            }
         }
     }
 }
 
+/// Highest field ID with a type of its own, those above are
+/// all `Unimplemented`
+const LAST_FIELD: u16 = 99;
+
 impl From<u16> for TemplateFieldType {
     fn from(field: u16) -> TemplateFieldType {
         match field {
             1 => TemplateFieldType::InBytes("InBytes", fmt_int),
             2 => TemplateFieldType::InPkts("InPackets", fmt_int),
             3 => TemplateFieldType::Flows("Flows", fmt_int),
-            4 => TemplateFieldType::Protocol("Protocol", fmt_int),
+            4 => TemplateFieldType::Protocol("Protocol", fmt_protocol),
             5 => TemplateFieldType::SrcTos("Src Tos", fmt_int),
             6 => TemplateFieldType::TCPFlags("TCP Flags", fmt_tcp_flags),
             7 => TemplateFieldType::L4SrcPort("L4 Src port", fmt_int),
@@ -280,7 +318,7 @@ impl From<u16> for TemplateFieldType {
             29 => TemplateFieldType::IPv6SrcMask("IPv6 Src Mask", fmt_int),
             30 => TemplateFieldType::IPv6DstMask("IPv6 Dst Mask", fmt_int),
             31 => TemplateFieldType::IPv6FlowLabel("IPv6 Flow Label", fmt_int),
-            32 => TemplateFieldType::ICMPType("ICMP type", fmt_int),
+            32 => TemplateFieldType::ICMPType("ICMP type", fmt_icmp),
             33 => {
                 TemplateFieldType::MulIGMPType("Multicast IGMP type", fmt_int)
             }
@@ -290,7 +328,7 @@ impl From<u16> for TemplateFieldType {
             ),
             35 => TemplateFieldType::SamplingAlgorithm(
                 "Sampling algorithm",
-                fmt_int,
+                fmt_sampling_algorithm,
             ),
             36 => TemplateFieldType::FlowActiveTimeout(
                 "Flow active timeout",
@@ -324,7 +362,7 @@ impl From<u16> for TemplateFieldType {
             48 => TemplateFieldType::FlowSamplerID("Sampler ID", fmt_int),
             49 => TemplateFieldType::FlowSamplerMode(
                 "Sampling algorithm",
-                fmt_int,
+                fmt_sampling_algorithm,
             ),
             50 => TemplateFieldType::FlowSamplerRandomInterval(
                 "Packet sample rate interval",
@@ -344,7 +382,7 @@ impl From<u16> for TemplateFieldType {
                 "IP Proto version",
                 fmt_int,
             ),
-            61 => TemplateFieldType::Direction("Direction", fmt_int),
+            61 => TemplateFieldType::Direction("Direction", fmt_direction),
             62 => TemplateFieldType::IPv6NextHop("IPv6 Next Hop", fmt_ipv6),
             63 => {
                 TemplateFieldType::BgpIPv6NextHop("BGP IPv6 Next Hop", fmt_ipv6)
@@ -372,7 +410,7 @@ impl From<u16> for TemplateFieldType {
 
             89 => TemplateFieldType::ForwardingStatus(
                 "Forwarding Status",
-                fmt_int,
+                fmt_forwarding_status,
             ),
 
             99 => TemplateFieldType::ReplicationFactor(
@@ -387,3 +425,267 @@ impl From<u16> for TemplateFieldType {
         }
     }
 }
+
+/// IANA IPFIX information element name of a field. The v9
+/// field IDs below 128 match the IPFIX element IDs.
+pub fn iana_name(field: u16) -> Option<&'static str> {
+    let name = match field {
+        1 => "octetDeltaCount",
+        2 => "packetDeltaCount",
+        3 => "deltaFlowCount",
+        4 => "protocolIdentifier",
+        5 => "ipClassOfService",
+        6 => "tcpControlBits",
+        7 => "sourceTransportPort",
+        8 => "sourceIPv4Address",
+        9 => "sourceIPv4PrefixLength",
+        10 => "ingressInterface",
+        11 => "destinationTransportPort",
+        12 => "destinationIPv4Address",
+        13 => "destinationIPv4PrefixLength",
+        14 => "egressInterface",
+        15 => "ipNextHopIPv4Address",
+        16 => "bgpSourceAsNumber",
+        17 => "bgpDestinationAsNumber",
+        18 => "bgpNextHopIPv4Address",
+        19 => "postMCastPacketDeltaCount",
+        20 => "postMCastOctetDeltaCount",
+        21 => "flowEndSysUpTime",
+        22 => "flowStartSysUpTime",
+        23 => "postOctetDeltaCount",
+        24 => "postPacketDeltaCount",
+        25 => "minimumIpTotalLength",
+        26 => "maximumIpTotalLength",
+        27 => "sourceIPv6Address",
+        28 => "destinationIPv6Address",
+        29 => "sourceIPv6PrefixLength",
+        30 => "destinationIPv6PrefixLength",
+        31 => "flowLabelIPv6",
+        32 => "icmpTypeCodeIPv4",
+        33 => "igmpType",
+        34 => "samplingInterval",
+        35 => "samplingAlgorithm",
+        36 => "flowActiveTimeout",
+        37 => "flowIdleTimeout",
+        38 => "engineType",
+        39 => "engineId",
+        40 => "exportedOctetTotalCount",
+        41 => "exportedMessageTotalCount",
+        42 => "exportedFlowRecordTotalCount",
+        44 => "sourceIPv4Prefix",
+        45 => "destinationIPv4Prefix",
+        46 => "mplsTopLabelType",
+        47 => "mplsTopLabelIPv4Address",
+        48 => "samplerId",
+        49 => "samplerMode",
+        50 => "samplerRandomInterval",
+        52 => "minimumTTL",
+        53 => "maximumTTL",
+        54 => "fragmentIdentification",
+        56 => "sourceMacAddress",
+        57 => "postDestinationMacAddress",
+        58 => "vlanId",
+        59 => "postVlanId",
+        60 => "ipVersion",
+        61 => "flowDirection",
+        62 => "ipNextHopIPv6Address",
+        63 => "bgpNextHopIPv6Address",
+        64 => "ipv6ExtensionHeaders",
+        70 => "mplsTopLabelStackSection",
+        71 => "mplsLabelStackSection2",
+        72 => "mplsLabelStackSection3",
+        73 => "mplsLabelStackSection4",
+        74 => "mplsLabelStackSection5",
+        75 => "mplsLabelStackSection6",
+        76 => "mplsLabelStackSection7",
+        77 => "mplsLabelStackSection8",
+        78 => "mplsLabelStackSection9",
+        79 => "mplsLabelStackSection10",
+        80 => "destinationMacAddress",
+        81 => "postSourceMacAddress",
+        82 => "interfaceName",
+        83 => "interfaceDescription",
+        89 => "forwardingStatus",
+        99 => "replicationFactor",
+        _ => return None,
+    };
+    Some(name)
+}
+
+/// nfdump short name of a field. IPv4 and IPv6 addresses
+/// share a name, like in nfdump itself.
+pub fn nfdump_name(field: u16) -> Option<&'static str> {
+    let name = match field {
+        1 => "ibyt",
+        2 => "ipkt",
+        3 => "fl",
+        4 => "pr",
+        5 => "stos",
+        6 => "flg",
+        7 => "sp",
+        8 => "sa",
+        9 => "smk",
+        10 => "in",
+        11 => "dp",
+        12 => "da",
+        13 => "dmk",
+        14 => "out",
+        15 => "nh",
+        16 => "sas",
+        17 => "das",
+        18 => "nhb",
+        21 => "te",
+        22 => "ts",
+        23 => "obyt",
+        24 => "opkt",
+        27 => "sa",
+        28 => "da",
+        29 => "smk",
+        30 => "dmk",
+        56 => "ismc",
+        57 => "odmc",
+        58 => "svln",
+        59 => "dvln",
+        61 => "dir",
+        62 => "nh",
+        63 => "nhb",
+        70 => "mpls1",
+        71 => "mpls2",
+        72 => "mpls3",
+        73 => "mpls4",
+        74 => "mpls5",
+        75 => "mpls6",
+        76 => "mpls7",
+        77 => "mpls8",
+        78 => "mpls9",
+        79 => "mpls10",
+        80 => "idmc",
+        81 => "osmc",
+        89 => "fwd",
+        _ => return None,
+    };
+    Some(name)
+}
+
+/// Name a field according to the requested key style.
+/// Styles without a name for the field fall back to the
+/// snake_case label, or `field_<id>` for unknown fields so
+/// that keys stay unique.
+pub fn field_key(field: u16, style: KeyStyle) -> Cow<'static, str> {
+    let label = || match TemplateFieldType::from(field) {
+        TemplateFieldType::Unimplemented(..) => format!("field_{}", field),
+        known => known.label().to_lowercase().replace(' ', "_"),
+    };
+    match style {
+        KeyStyle::Label => TemplateFieldType::from(field).label().into(),
+        KeyStyle::Iana => {
+            iana_name(field).map_or_else(|| label().into(), Cow::from)
+        }
+        KeyStyle::SnakeCase => label().into(),
+        KeyStyle::Nfdump => {
+            nfdump_name(field).map_or_else(|| label().into(), Cow::from)
+        }
+        KeyStyle::Id => field.to_string().into(),
+    }
+}
+
+/// Look a field up by any of its names: label, IANA name,
+/// snake_case label, nfdump name or numeric ID. nfdump names
+/// shared by IPv4 and IPv6 fields, such as `sa` and `da`,
+/// give the IPv4 field; the IPv6 one is found by its other
+/// names, e.g. `sourceIPv6Address`.
+pub fn field_from_name(name: &str) -> Option<u16> {
+    if let Ok(id) = name.parse::<u16>() {
+        return Some(id);
+    }
+    if let Some(id) = name.strip_prefix("field_") {
+        return id.parse().ok();
+    }
+    let names = FIELD_NAMES.get_or_init(FieldNames::new);
+    let label = names.labels.get(&name.to_ascii_lowercase());
+    label
+        .into_iter()
+        .chain(names.names.get(name))
+        .min()
+        .copied()
+}
+
+/// Names of the implemented fields, built on first lookup. A
+/// name shared by several fields gives the lowest ID.
+struct FieldNames {
+    /// Lowercase labels, matched regardless of case
+    labels: HashMap<String, u16>,
+    /// IANA, nfdump and snake_case names
+    names: HashMap<Cow<'static, str>, u16>,
+}
+
+static FIELD_NAMES: OnceLock<FieldNames> = OnceLock::new();
+
+impl FieldNames {
+    fn new() -> Self {
+        let mut labels = HashMap::new();
+        let mut names = HashMap::new();
+        for f in 1..=LAST_FIELD {
+            let field = TemplateFieldType::from(f);
+            if let TemplateFieldType::Unimplemented(..) = field {
+                continue;
+            }
+            labels
+                .entry(field.label().to_ascii_lowercase())
+                .or_insert(f);
+            let aliases = iana_name(f).into_iter().chain(nfdump_name(f));
+            for name in aliases.map(Cow::Borrowed) {
+                names.entry(name).or_insert(f);
+            }
+            names.entry(field_key(f, KeyStyle::SnakeCase)).or_insert(f);
+        }
+        FieldNames { labels, names }
+    }
+}
+
+/// What kind of value a field carries, independently of
+/// how it is formatted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldKind {
+    /// Counters, identifiers and enumerations
+    Unsigned,
+    Ipv4,
+    Ipv6,
+    Mac,
+    Text,
+    /// Milliseconds of exporter uptime
+    Uptime,
+}
+
+pub fn field_kind(field: u16) -> FieldKind {
+    match field {
+        8 | 12 | 15 | 18 | 44 | 45 | 47 => FieldKind::Ipv4,
+        27 | 28 | 62 | 63 => FieldKind::Ipv6,
+        56 | 57 | 80 | 81 => FieldKind::Mac,
+        82 | 83 => FieldKind::Text,
+        21 | 22 => FieldKind::Uptime,
+        _ => FieldKind::Unsigned,
+    }
+}
+
+/// IANA name of a field in snake_case, e.g.
+/// `source_ipv4_address`
+pub fn iana_snake_name(field: u16) -> Option<String> {
+    let name = iana_name(field)?
+        .replace("IPv", "Ipv")
+        .replace("MCast", "Mcast");
+    let chars: Vec<char> = name.chars().collect();
+    let mut snake = String::with_capacity(name.len() + 4);
+    for (i, c) in chars.iter().enumerate() {
+        if c.is_ascii_uppercase() && i > 0 {
+            let prev = chars[i - 1];
+            let next_lower =
+                chars.get(i + 1).is_some_and(|n| n.is_ascii_lowercase());
+            if !prev.is_ascii_uppercase() || next_lower {
+                snake.push('_');
+            }
+        }
+        snake.push(c.to_ascii_lowercase());
+    }
+    Some(snake)
+}
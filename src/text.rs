@@ -0,0 +1,370 @@
+use crate::formaters::{
+    fmt_rfc3339_millis, protocol_name, read_uint, EnumStyle,
+};
+use crate::resolve_hashmap::{decode, is_ipv6};
+use crate::templates::{field_from_name, TemplateFieldType};
+use crate::DataFlowset;
+use std::convert::TryFrom;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// A value a format token stands for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Item {
+    StartTime,
+    EndTime,
+    Duration,
+    Protocol,
+    SrcAddr,
+    DstAddr,
+    SrcAddrPort,
+    DstAddrPort,
+    SrcPort,
+    DstPort,
+    SrcNet,
+    DstNet,
+    Packets,
+    Bytes,
+    Flows,
+    Flags,
+    Tos,
+    InputIf,
+    OutputIf,
+    SrcAs,
+    DstAs,
+    NextHop,
+    Router,
+    Bps,
+    Pps,
+    Bpp,
+    /// Any registry field, `%{name}`
+    Field(u16),
+}
+
+/// Token, item, column header, width and whether the column
+/// is right aligned
+const TOKENS: &[(&str, Item, &str, usize, bool)] = &[
+    ("ts", Item::StartTime, "Date first seen", 23, false),
+    ("te", Item::EndTime, "Date last seen", 23, false),
+    ("td", Item::Duration, "Duration", 9, true),
+    ("pr", Item::Protocol, "Proto", 5, false),
+    ("sa", Item::SrcAddr, "Src IP Addr", 16, false),
+    ("da", Item::DstAddr, "Dst IP Addr", 16, false),
+    ("sap", Item::SrcAddrPort, "Src IP Addr:Port", 21, false),
+    ("dap", Item::DstAddrPort, "Dst IP Addr:Port", 21, false),
+    ("sp", Item::SrcPort, "Src Pt", 6, true),
+    ("dp", Item::DstPort, "Dst Pt", 6, true),
+    ("sn", Item::SrcNet, "Src Network", 18, false),
+    ("dn", Item::DstNet, "Dst Network", 18, false),
+    ("pkt", Item::Packets, "Packets", 8, true),
+    ("byt", Item::Bytes, "Bytes", 8, true),
+    ("fl", Item::Flows, "Flows", 5, true),
+    ("flg", Item::Flags, "Flags", 6, false),
+    ("tos", Item::Tos, "Tos", 3, true),
+    ("in", Item::InputIf, "Input", 5, true),
+    ("out", Item::OutputIf, "Output", 6, true),
+    ("sas", Item::SrcAs, "Src AS", 6, true),
+    ("das", Item::DstAs, "Dst AS", 6, true),
+    ("nh", Item::NextHop, "Next-hop IP", 16, false),
+    ("ra", Item::Router, "Router IP", 15, false),
+    ("bps", Item::Bps, "bps", 8, true),
+    ("pps", Item::Pps, "pps", 8, true),
+    ("bpp", Item::Bpp, "Bpp", 6, true),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Literal(String),
+    Item(Item),
+}
+
+/// Formats flowsets as text lines driven by nfdump style
+/// format strings, e.g. `%ts %td %pr %sap -> %dap %pkt %byt`.
+/// Besides the nfdump tokens, `%{name}` prints any field by
+/// one of its names (see `Column::from_name`) and `%%` a
+/// literal `%`. Times are printed in UTC.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextFormat {
+    tokens: Vec<Token>,
+}
+
+impl TextFormat {
+    pub const LINE: &'static str = "%ts %td %pr %sap -> %dap %pkt %byt %fl";
+    pub const LONG: &'static str =
+        "%ts %td %pr %sap -> %dap %flg %tos %pkt %byt %fl";
+    pub const EXTENDED: &'static str =
+        "%ts %td %pr %sap -> %dap %flg %tos %pkt %byt %pps %bps %bpp %fl";
+
+    pub fn new(format: &str) -> Result<Self, &'static str> {
+        let mut tokens = Vec::new();
+        let mut literal = String::new();
+        let mut rest = format;
+        while let Some(pos) = rest.find('%') {
+            literal.push_str(&rest[..pos]);
+            rest = &rest[pos + 1..];
+            let item = if let Some(r) = rest.strip_prefix('%') {
+                literal.push('%');
+                rest = r;
+                continue;
+            } else if let Some(r) = rest.strip_prefix('{') {
+                let end = r.find('}').ok_or("Unterminated field name")?;
+                let field =
+                    field_from_name(&r[..end]).ok_or("Unknown field")?;
+                rest = &r[end + 1..];
+                Item::Field(field)
+            } else {
+                // Longest token first, `%sap` before `%sa`
+                let (token, item, ..) = TOKENS
+                    .iter()
+                    .filter(|t| rest.starts_with(t.0))
+                    .max_by_key(|t| t.0.len())
+                    .ok_or("Unknown format token")?;
+                rest = &rest[token.len()..];
+                *item
+            };
+            if !literal.is_empty() {
+                tokens.push(Token::Literal(std::mem::take(&mut literal)));
+            }
+            tokens.push(Token::Item(item));
+        }
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            tokens.push(Token::Literal(literal));
+        }
+        Ok(TextFormat { tokens })
+    }
+
+    pub fn line() -> Self {
+        TextFormat::new(TextFormat::LINE).unwrap()
+    }
+
+    pub fn long() -> Self {
+        TextFormat::new(TextFormat::LONG).unwrap()
+    }
+
+    pub fn extended() -> Self {
+        TextFormat::new(TextFormat::EXTENDED).unwrap()
+    }
+
+    /// Look up a format the way `nfdump -o` does: `line`,
+    /// `long`, `extended` or `fmt:<format string>`
+    pub fn from_name(name: &str) -> Result<Self, &'static str> {
+        match name {
+            "line" => Ok(TextFormat::line()),
+            "long" => Ok(TextFormat::long()),
+            "extended" => Ok(TextFormat::extended()),
+            _ => match name.strip_prefix("fmt:") {
+                Some(format) => TextFormat::new(format),
+                None => Err("Unknown output format"),
+            },
+        }
+    }
+
+    /// Column headers aligned with the formatted lines
+    pub fn header(&self) -> String {
+        let mut line = String::new();
+        for token in &self.tokens {
+            match token {
+                // Keep the spacing but not the decoration
+                Token::Literal(s) => line.extend(s.chars().map(|c| {
+                    if c.is_whitespace() {
+                        c
+                    } else {
+                        ' '
+                    }
+                })),
+                Token::Item(Item::Field(f)) => {
+                    line.push_str(TemplateFieldType::from(*f).label())
+                }
+                Token::Item(item) => {
+                    let &(_, _, header, width, right) = column(*item);
+                    pad(&mut line, header, width, right)
+                }
+            }
+        }
+        line.trim_end().to_owned()
+    }
+
+    pub fn format(&self, set: &DataFlowset) -> String {
+        let mut line = String::new();
+        for token in &self.tokens {
+            match token {
+                Token::Literal(s) => line.push_str(s),
+                Token::Item(Item::Field(f)) => {
+                    line.push_str(&item_value(set, Item::Field(*f)))
+                }
+                Token::Item(item) => {
+                    let &(_, _, _, width, right) = column(*item);
+                    pad(&mut line, &item_value(set, *item), width, right)
+                }
+            }
+        }
+        line.trim_end().to_owned()
+    }
+
+    pub fn write_header<W: io::Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "{}", self.header())
+    }
+
+    pub fn write_record<W: io::Write>(
+        &self,
+        set: &DataFlowset,
+        mut writer: W,
+    ) -> io::Result<()> {
+        writeln!(writer, "{}", self.format(set))
+    }
+
+    pub fn write_records<W: io::Write>(
+        &self,
+        sets: &[DataFlowset],
+        mut writer: W,
+    ) -> io::Result<()> {
+        for set in sets {
+            self.write_record(set, &mut writer)?;
+        }
+        Ok(())
+    }
+}
+
+fn column(
+    item: Item,
+) -> &'static (&'static str, Item, &'static str, usize, bool) {
+    TOKENS.iter().find(|t| t.1 == item).unwrap()
+}
+
+fn pad(line: &mut String, value: &str, width: usize, right: bool) {
+    if right {
+        line.push_str(&format!("{:>1$}", value, width))
+    } else {
+        line.push_str(&format!("{:<1$}", value, width))
+    }
+}
+
+fn ip(v: &[u8]) -> Option<IpAddr> {
+    match v.len() {
+        4 => {
+            let mut octets = [0; 4];
+            octets.copy_from_slice(v);
+            Some(IpAddr::V4(Ipv4Addr::from(octets)))
+        }
+        16 => {
+            let mut octets = [0; 16];
+            octets.copy_from_slice(v);
+            Some(IpAddr::V6(Ipv6Addr::from(octets)))
+        }
+        _ => None,
+    }
+}
+
+/// Network address of `ip` with a prefix of `len` bits
+fn network(ip: IpAddr, len: u32) -> IpAddr {
+    match ip {
+        IpAddr::V4(ip) => {
+            let mask = u32::MAX.checked_shl(32 - len.min(32)).unwrap_or(0);
+            IpAddr::V4(Ipv4Addr::from(u32::from(ip) & mask))
+        }
+        IpAddr::V6(ip) => {
+            let mask = u128::MAX.checked_shl(128 - len.min(128)).unwrap_or(0);
+            IpAddr::V6(Ipv6Addr::from(u128::from(ip) & mask))
+        }
+    }
+}
+
+/// TCP flags the way nfdump prints them, e.g. `.AP.SF`
+fn tcp_flags(flags: u64) -> String {
+    "UAPRSF"
+        .chars()
+        .enumerate()
+        .map(|(i, c)| if flags & (0x20 >> i) != 0 { c } else { '.' })
+        .collect()
+}
+
+fn item_value(set: &DataFlowset, item: Item) -> String {
+    let ipv6 = is_ipv6(&set.records);
+    let uint = |field: u16| set.records.get(&field).and_then(|v| read_uint(v));
+    let addr = |v4: u16, v6: u16| {
+        set.records
+            .get(&v4)
+            .or_else(|| set.records.get(&v6))
+            .and_then(|v| ip(v))
+    };
+    let (start, end) = (set.flow_start_millis(), set.flow_end_millis());
+    let duration = match (start, end) {
+        (Some(start), Some(end)) => Some(end.saturating_sub(start)),
+        _ => None,
+    };
+    // In u128 so that 8 byte counters can't overflow, rates
+    // beyond u64 saturate
+    let per_second = |n: Option<u64>, scale: u64| match (n, duration) {
+        (Some(n), Some(ms)) if ms > 0 => {
+            let rate =
+                u128::from(n) * u128::from(scale) * 1000 / u128::from(ms);
+            Some(u64::try_from(rate).unwrap_or(u64::MAX))
+        }
+        (Some(_), Some(_)) => Some(0),
+        _ => None,
+    };
+    let addr_port = |v4: u16, v6: u16, port: u16| {
+        addr(v4, v6).map(|ip| match (ip, uint(port)) {
+            (IpAddr::V4(ip), Some(port)) => format!("{}:{}", ip, port),
+            (IpAddr::V6(ip), Some(port)) => format!("[{}]:{}", ip, port),
+            (ip, None) => ip.to_string(),
+        })
+    };
+    let net = |v4: u16, v6: u16, mask4: u16, mask6: u16| {
+        let len = uint(mask4).or_else(|| uint(mask6))?;
+        let ip = addr(v4, v6)?;
+        Some(format!("{}/{}", network(ip, len as u32), len))
+    };
+    let time = |ms: Option<u64>| {
+        ms.map(|ms| {
+            fmt_rfc3339_millis(ms)
+                .replace('T', " ")
+                .trim_end_matches('Z')
+                .to_owned()
+        })
+    };
+
+    let value = match item {
+        Item::StartTime => time(start),
+        Item::EndTime => time(end),
+        Item::Duration => {
+            duration.map(|ms| format!("{}.{:03}", ms / 1000, ms % 1000))
+        }
+        Item::Protocol => uint(4).map(|p| match protocol_name(p) {
+            Some(name) => name.to_owned(),
+            None => p.to_string(),
+        }),
+        Item::SrcAddr => addr(8, 27).map(|ip| ip.to_string()),
+        Item::DstAddr => addr(12, 28).map(|ip| ip.to_string()),
+        Item::SrcAddrPort => addr_port(8, 27, 7),
+        Item::DstAddrPort => addr_port(12, 28, 11),
+        Item::SrcPort => uint(7).map(|n| n.to_string()),
+        Item::DstPort => uint(11).map(|n| n.to_string()),
+        Item::SrcNet => net(8, 27, 9, 29),
+        Item::DstNet => net(12, 28, 13, 30),
+        Item::Packets => uint(2).map(|n| n.to_string()),
+        Item::Bytes => uint(1).map(|n| n.to_string()),
+        Item::Flows => Some(uint(3).unwrap_or(1).to_string()),
+        Item::Flags => uint(6).map(tcp_flags),
+        Item::Tos => uint(5).map(|n| n.to_string()),
+        Item::InputIf => uint(10).map(|n| n.to_string()),
+        Item::OutputIf => uint(14).map(|n| n.to_string()),
+        Item::SrcAs => uint(16).map(|n| n.to_string()),
+        Item::DstAs => uint(17).map(|n| n.to_string()),
+        Item::NextHop => addr(15, 62).map(|ip| ip.to_string()),
+        Item::Router => set.source_ip.map(|ip| ip.to_string()),
+        Item::Bps => per_second(uint(1), 8).map(|n| n.to_string()),
+        Item::Pps => per_second(uint(2), 1).map(|n| n.to_string()),
+        Item::Bpp => match (uint(1), uint(2)) {
+            (Some(bytes), Some(packets)) if packets > 0 => {
+                Some((bytes / packets).to_string())
+            }
+            _ => None,
+        },
+        Item::Field(f) => set.records.get(&f).map(|v| {
+            decode(f, v, ipv6).with_style(EnumStyle::Name).to_string()
+        }),
+    };
+    value.unwrap_or_else(|| "-".to_owned())
+}
@@ -1,73 +1,74 @@
 #[cfg(test)]
+#[allow(deprecated)] // `to_json` is still tested
 mod tests {
     extern crate netflow_v9;
     use std::net::{IpAddr, Ipv4Addr};
 
-    use self::netflow_v9::Parser;
+    use self::netflow_v9::{
+        Column, CsvWriter, EnumStyle, FormatOptions, InfluxConfig,
+        InfluxWriter, JsonBatch, KeyStyle, OwnedFlowRecord, Parser,
+        SharedTemplateCache, TemplateStore, TextFormat,
+    };
 
-    #[test]
-    fn test_parse() {
-        let packet_1: [u8; 230] = [
-            0x00, 0x09, 0x00, 0x04, 0x63, 0x76, 0x26, 0xee, 0x5a, 0x20, 0x85,
-            0x54, 0x00, 0x01, 0xef, 0xb7, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-            0x00, 0x68, 0x01, 0x01, 0x00, 0x18, 0x00, 0x08, 0x00, 0x04, 0x00,
-            0x0c, 0x00, 0x04, 0x00, 0x0f, 0x00, 0x04, 0x00, 0x12, 0x00, 0x04,
-            0x00, 0x0a, 0x00, 0x02, 0x00, 0x0e, 0x00, 0x02, 0x00, 0x02, 0x00,
-            0x04, 0x00, 0x01, 0x00, 0x04, 0x00, 0x16, 0x00, 0x04, 0x00, 0x15,
-            0x00, 0x04, 0x00, 0x07, 0x00, 0x02, 0x00, 0x0b, 0x00, 0x02, 0x00,
-            0x59, 0x00, 0x01, 0x00, 0x06, 0x00, 0x01, 0x00, 0x04, 0x00, 0x01,
-            0x00, 0x05, 0x00, 0x01, 0x00, 0x3c, 0x00, 0x01, 0x00, 0x3d, 0x00,
-            0x01, 0x00, 0x20, 0x00, 0x02, 0x00, 0x10, 0x00, 0x04, 0x00, 0x11,
-            0x00, 0x04, 0x00, 0x09, 0x00, 0x01, 0x00, 0x0d, 0x00, 0x01, 0x00,
-            0x63, 0x00, 0x04, 0x00, 0x01, 0x00, 0x18, 0x01, 0x00, 0x00, 0x04,
-            0x00, 0x08, 0x00, 0x01, 0x00, 0x04, 0x00, 0x23, 0x00, 0x01, 0x00,
-            0x22, 0x00, 0x04, 0x00, 0x00, 0x01, 0x00, 0x00, 0x10, 0x00, 0x00,
-            0x00, 0x00, 0x01, 0x00, 0x00, 0x03, 0xe8, 0x00, 0x00, 0x00, 0x01,
-            0x01, 0x00, 0x42, 0xb9, 0xa7, 0xc4, 0x7f, 0xb9, 0xa7, 0xc4, 0x7e,
-            0x00, 0x00, 0x00, 0x00, 0xb9, 0xa7, 0xc4, 0x7e, 0x00, 0x00, 0x01,
-            0x14, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x34, 0x63, 0x75,
-            0x04, 0xee, 0x63, 0x75, 0x04, 0xee, 0xc0, 0x00, 0x0e, 0xc8, 0x42,
-            0x00, 0x11, 0xc0, 0x04, 0x01, 0x00, 0x00, 0x00, 0x03, 0x28, 0xc4,
-            0x00, 0x03, 0x28, 0xc4, 0x20, 0x1f, 0x00, 0x00, 0x00, 0x00,
-        ];
+    const PACKET_1: [u8; 230] = [
+        0x00, 0x09, 0x00, 0x04, 0x63, 0x76, 0x26, 0xee, 0x5a, 0x20, 0x85, 0x54,
+        0x00, 0x01, 0xef, 0xb7, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x68,
+        0x01, 0x01, 0x00, 0x18, 0x00, 0x08, 0x00, 0x04, 0x00, 0x0c, 0x00, 0x04,
+        0x00, 0x0f, 0x00, 0x04, 0x00, 0x12, 0x00, 0x04, 0x00, 0x0a, 0x00, 0x02,
+        0x00, 0x0e, 0x00, 0x02, 0x00, 0x02, 0x00, 0x04, 0x00, 0x01, 0x00, 0x04,
+        0x00, 0x16, 0x00, 0x04, 0x00, 0x15, 0x00, 0x04, 0x00, 0x07, 0x00, 0x02,
+        0x00, 0x0b, 0x00, 0x02, 0x00, 0x59, 0x00, 0x01, 0x00, 0x06, 0x00, 0x01,
+        0x00, 0x04, 0x00, 0x01, 0x00, 0x05, 0x00, 0x01, 0x00, 0x3c, 0x00, 0x01,
+        0x00, 0x3d, 0x00, 0x01, 0x00, 0x20, 0x00, 0x02, 0x00, 0x10, 0x00, 0x04,
+        0x00, 0x11, 0x00, 0x04, 0x00, 0x09, 0x00, 0x01, 0x00, 0x0d, 0x00, 0x01,
+        0x00, 0x63, 0x00, 0x04, 0x00, 0x01, 0x00, 0x18, 0x01, 0x00, 0x00, 0x04,
+        0x00, 0x08, 0x00, 0x01, 0x00, 0x04, 0x00, 0x23, 0x00, 0x01, 0x00, 0x22,
+        0x00, 0x04, 0x00, 0x00, 0x01, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00,
+        0x01, 0x00, 0x00, 0x03, 0xe8, 0x00, 0x00, 0x00, 0x01, 0x01, 0x00, 0x42,
+        0xb9, 0xa7, 0xc4, 0x7f, 0xb9, 0xa7, 0xc4, 0x7e, 0x00, 0x00, 0x00, 0x00,
+        0xb9, 0xa7, 0xc4, 0x7e, 0x00, 0x00, 0x01, 0x14, 0x00, 0x00, 0x00, 0x01,
+        0x00, 0x00, 0x00, 0x34, 0x63, 0x75, 0x04, 0xee, 0x63, 0x75, 0x04, 0xee,
+        0xc0, 0x00, 0x0e, 0xc8, 0x42, 0x00, 0x11, 0xc0, 0x04, 0x01, 0x00, 0x00,
+        0x00, 0x03, 0x28, 0xc4, 0x00, 0x03, 0x28, 0xc4, 0x20, 0x1f, 0x00, 0x00,
+        0x00, 0x00,
+    ];
 
-        let packet_2: [u8; 310] = [
-            0x00, 0x09, 0x00, 0x04, 0x63, 0x7a, 0x70, 0x96, 0x5a, 0x20, 0x86,
-            0x6d, 0x00, 0x01, 0xef, 0xbb, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-            0x00, 0x78, 0x01, 0x05, 0x00, 0x1c, 0x00, 0x1b, 0x00, 0x10, 0x00,
-            0x1c, 0x00, 0x10, 0x00, 0x3e, 0x00, 0x10, 0x00, 0x3f, 0x00, 0x10,
-            0x00, 0x0f, 0x00, 0x04, 0x00, 0x12, 0x00, 0x04, 0x00, 0x0a, 0x00,
-            0x02, 0x00, 0x0e, 0x00, 0x02, 0x00, 0x02, 0x00, 0x04, 0x00, 0x01,
-            0x00, 0x04, 0x00, 0x16, 0x00, 0x04, 0x00, 0x15, 0x00, 0x04, 0x00,
-            0x07, 0x00, 0x02, 0x00, 0x0b, 0x00, 0x02, 0x00, 0x59, 0x00, 0x01,
-            0x00, 0x06, 0x00, 0x01, 0x00, 0x04, 0x00, 0x01, 0x00, 0x40, 0x00,
-            0x04, 0x00, 0x1f, 0x00, 0x04, 0x00, 0x05, 0x00, 0x01, 0x00, 0x3c,
-            0x00, 0x01, 0x00, 0x3d, 0x00, 0x01, 0x00, 0x20, 0x00, 0x02, 0x00,
-            0x10, 0x00, 0x04, 0x00, 0x11, 0x00, 0x04, 0x00, 0x1d, 0x00, 0x01,
-            0x00, 0x1e, 0x00, 0x01, 0x00, 0x63, 0x00, 0x04, 0x00, 0x01, 0x00,
-            0x18, 0x01, 0x00, 0x00, 0x04, 0x00, 0x08, 0x00, 0x01, 0x00, 0x04,
-            0x00, 0x23, 0x00, 0x01, 0x00, 0x22, 0x00, 0x04, 0x00, 0x00, 0x01,
-            0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x03,
-            0xe8, 0x00, 0x00, 0x00, 0x01, 0x05, 0x00, 0x82, 0x2a, 0x0b, 0xae,
-            0x00, 0x01, 0x21, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-            0x00, 0x04, 0x2a, 0x0b, 0xae, 0x00, 0x01, 0x21, 0x00, 0x00, 0x00,
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x05, 0x00, 0x00, 0x00, 0x00,
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-            0x00, 0x2a, 0x0b, 0xae, 0x00, 0x01, 0x21, 0x00, 0x00, 0x00, 0x00,
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x05, 0x00, 0x00, 0x00, 0x00, 0x00,
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x14, 0x00, 0x00, 0x00, 0x01,
-            0x00, 0x00, 0x00, 0x48, 0x63, 0x79, 0x4d, 0xec, 0x63, 0x79, 0x4d,
-            0xec, 0xc0, 0x00, 0x0e, 0xc8, 0x42, 0x00, 0x11, 0x00, 0x00, 0x00,
-            0x00, 0x00, 0x00, 0x00, 0x00, 0xc0, 0x06, 0x01, 0x00, 0x00, 0x00,
-            0x03, 0x28, 0xc4, 0x00, 0x03, 0x28, 0xc4, 0x80, 0x7f, 0x00, 0x00,
-            0x00, 0x00,
-        ];
+    const PACKET_2: [u8; 310] = [
+        0x00, 0x09, 0x00, 0x04, 0x63, 0x7a, 0x70, 0x96, 0x5a, 0x20, 0x86, 0x6d,
+        0x00, 0x01, 0xef, 0xbb, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x78,
+        0x01, 0x05, 0x00, 0x1c, 0x00, 0x1b, 0x00, 0x10, 0x00, 0x1c, 0x00, 0x10,
+        0x00, 0x3e, 0x00, 0x10, 0x00, 0x3f, 0x00, 0x10, 0x00, 0x0f, 0x00, 0x04,
+        0x00, 0x12, 0x00, 0x04, 0x00, 0x0a, 0x00, 0x02, 0x00, 0x0e, 0x00, 0x02,
+        0x00, 0x02, 0x00, 0x04, 0x00, 0x01, 0x00, 0x04, 0x00, 0x16, 0x00, 0x04,
+        0x00, 0x15, 0x00, 0x04, 0x00, 0x07, 0x00, 0x02, 0x00, 0x0b, 0x00, 0x02,
+        0x00, 0x59, 0x00, 0x01, 0x00, 0x06, 0x00, 0x01, 0x00, 0x04, 0x00, 0x01,
+        0x00, 0x40, 0x00, 0x04, 0x00, 0x1f, 0x00, 0x04, 0x00, 0x05, 0x00, 0x01,
+        0x00, 0x3c, 0x00, 0x01, 0x00, 0x3d, 0x00, 0x01, 0x00, 0x20, 0x00, 0x02,
+        0x00, 0x10, 0x00, 0x04, 0x00, 0x11, 0x00, 0x04, 0x00, 0x1d, 0x00, 0x01,
+        0x00, 0x1e, 0x00, 0x01, 0x00, 0x63, 0x00, 0x04, 0x00, 0x01, 0x00, 0x18,
+        0x01, 0x00, 0x00, 0x04, 0x00, 0x08, 0x00, 0x01, 0x00, 0x04, 0x00, 0x23,
+        0x00, 0x01, 0x00, 0x22, 0x00, 0x04, 0x00, 0x00, 0x01, 0x00, 0x00, 0x10,
+        0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x03, 0xe8, 0x00, 0x00, 0x00,
+        0x01, 0x05, 0x00, 0x82, 0x2a, 0x0b, 0xae, 0x00, 0x01, 0x21, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x04, 0x2a, 0x0b, 0xae, 0x00,
+        0x01, 0x21, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x05,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x2a, 0x0b, 0xae, 0x00, 0x01, 0x21, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x05, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x14, 0x00, 0x00, 0x00, 0x01,
+        0x00, 0x00, 0x00, 0x48, 0x63, 0x79, 0x4d, 0xec, 0x63, 0x79, 0x4d, 0xec,
+        0xc0, 0x00, 0x0e, 0xc8, 0x42, 0x00, 0x11, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0xc0, 0x06, 0x01, 0x00, 0x00, 0x00, 0x03, 0x28, 0xc4,
+        0x00, 0x03, 0x28, 0xc4, 0x80, 0x7f, 0x00, 0x00, 0x00, 0x00,
+    ];
 
+    #[test]
+    fn test_parse() {
         let mut parser = Parser::new();
 
         let addr = IpAddr::V4(Ipv4Addr::new(192, 168, 100, 1));
 
-        if let Ok(sets) = parser.parse_netflow_packet(&packet_1, &addr) {
+        if let Ok(sets) = parser.parse_netflow_packet(&PACKET_1, &addr) {
             for set in sets {
                 let s = set.to_json();
 
@@ -102,7 +103,7 @@ mod tests {
             }
         }
 
-        if let Ok(sets) = parser.parse_netflow_packet(&packet_2, &addr) {
+        if let Ok(sets) = parser.parse_netflow_packet(&PACKET_2, &addr) {
             for set in sets {
                 let s = set.to_json();
 
@@ -139,4 +140,1434 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_parse_icmp() {
+        let addr = IpAddr::V4(Ipv4Addr::new(192, 168, 100, 1));
+
+        // Turn the IPv4 flow into ICMP port unreachable (type 3, code 3)
+        let mut packet = PACKET_1;
+        let icmp = packet.len() - 16;
+        packet[icmp - 4] = 1;
+        packet[icmp] = 3;
+        packet[icmp + 1] = 3;
+
+        let mut parser = Parser::new();
+        let sets = parser.parse_netflow_packet(&packet, &addr).unwrap();
+        assert_eq!(sets.len(), 1);
+        let s = sets[0].to_json();
+        assert!(s.contains("\"Protocol\":1"));
+        assert!(s.contains("\"ICMP type\":771"));
+        parser.set_format_options(FormatOptions {
+            enums: EnumStyle::Name,
+            ..Default::default()
+        });
+        let sets = parser.parse_netflow_packet(&packet, &addr).unwrap();
+        let s = sets[0].to_json();
+        assert!(s.contains(
+            "\"ICMP type\":\"Destination Unreachable/Port Unreachable\""
+        ));
+        parser.set_format_options(FormatOptions {
+            enums: EnumStyle::Both,
+            ..Default::default()
+        });
+        let sets = parser.parse_netflow_packet(&packet, &addr).unwrap();
+        let s = sets[0].to_json();
+        assert!(s.contains(
+            "\"ICMP type\":{\"type\":3,\"code\":3,\"type_name\":\"Destination Unreachable\",\"code_name\":\"Port Unreachable\"}"
+        ));
+
+        // Same bytes in an IPv6 flow decode as ICMPv6 time exceeded
+        let mut packet = PACKET_2;
+        let icmp = packet.len() - 16;
+        packet[icmp] = 3;
+        packet[icmp + 1] = 0;
+
+        let mut parser = Parser::new();
+        parser.set_format_options(FormatOptions {
+            enums: EnumStyle::Both,
+            ..Default::default()
+        });
+        let sets = parser.parse_netflow_packet(&packet, &addr).unwrap();
+        let s = sets[0].to_json();
+        assert!(s.contains(
+            "\"ICMP type\":{\"type\":3,\"code\":0,\"type_name\":\"Time Exceeded\",\"code_name\":\"Hop Limit Exceeded in Transit\"}"
+        ));
+    }
+
+    #[test]
+    fn test_parse_enum_names() {
+        let addr = IpAddr::V4(Ipv4Addr::new(192, 168, 100, 1));
+
+        let mut parser = Parser::new();
+        parser.set_format_options(FormatOptions {
+            enums: EnumStyle::Name,
+            ..Default::default()
+        });
+        let sets = parser.parse_netflow_packet(&PACKET_1, &addr).unwrap();
+        let s = sets[0].to_json();
+        assert!(s.contains("\"Protocol\":\"UDP\""));
+        assert!(
+            s.contains("\"Forwarding Status\":\"Forwarded: Not Fragmented\"")
+        );
+        assert!(s.contains("\"Direction\":\"Egress\""));
+
+        let mut set = sets[0].clone();
+        set.set_format_options(FormatOptions {
+            enums: EnumStyle::Both,
+            ..Default::default()
+        });
+        let s = set.to_json();
+        assert!(s.contains("\"Protocol\":{\"code\":17,\"name\":\"UDP\"}"));
+        assert!(s.contains("\"Direction\":{\"code\":1,\"name\":\"Egress\"}"));
+    }
+
+    #[test]
+    fn test_parse_key_styles() {
+        let addr = IpAddr::V4(Ipv4Addr::new(192, 168, 100, 1));
+        let mut parser = Parser::new();
+        let sets = parser.parse_netflow_packet(&PACKET_1, &addr).unwrap();
+        let mut set = sets[0].clone();
+
+        let keys = |set: &mut netflow_v9::DataFlowset, keys| {
+            set.set_format_options(FormatOptions {
+                keys,
+                ..Default::default()
+            });
+            set.to_json()
+        };
+
+        let s = keys(&mut set, KeyStyle::Iana);
+        assert!(s.contains("\"sourceIPv4Address\":\"185.167.196.127\""));
+        assert!(s.contains("\"destinationTransportPort\":3784"));
+
+        let s = keys(&mut set, KeyStyle::SnakeCase);
+        assert!(s.contains("\"ipv4_src_addr\":\"185.167.196.127\""));
+        assert!(s.contains("\"l4_dst_port\":3784"));
+
+        let s = keys(&mut set, KeyStyle::Nfdump);
+        assert!(s.contains("\"sa\":\"185.167.196.127\""));
+        assert!(s.contains("\"dp\":3784"));
+
+        let s = keys(&mut set, KeyStyle::Id);
+        assert!(s.contains("\"8\":\"185.167.196.127\""));
+        assert!(s.contains("\"11\":3784"));
+
+        // Unknown fields keep distinct keys
+        let mut writer =
+            CsvWriter::new(vec![Column::Field(1000), Column::Field(1001)]);
+        for keys in [KeyStyle::Iana, KeyStyle::SnakeCase, KeyStyle::Nfdump] {
+            writer.set_format_options(FormatOptions {
+                keys,
+                ..Default::default()
+            });
+            let mut header = Vec::new();
+            writer.write_header(&mut header).unwrap();
+            assert_eq!(header, b"field_1000,field_1001\n");
+        }
+        assert_eq!(Column::from_name("field_1000"), Some(Column::Field(1000)));
+        assert_eq!(Column::from_name("sa"), Some(Column::Field(8)));
+        assert_eq!(
+            Column::from_name("sourceIPv6Address"),
+            Some(Column::Field(27))
+        );
+    }
+
+    #[test]
+    fn test_write_json() {
+        let addr = IpAddr::V4(Ipv4Addr::new(192, 168, 100, 1));
+        let mut parser = Parser::new();
+        let mut batch = JsonBatch::new();
+
+        for packet in [&PACKET_1[..], &PACKET_2[..]].iter() {
+            let sets = parser.parse_netflow_packet(packet, &addr).unwrap();
+            let mut out = Vec::new();
+            sets[0].write_json(&mut out).unwrap();
+            assert_eq!(out, sets[0].to_json().into_bytes());
+
+            batch.clear();
+            batch.write(&sets).unwrap();
+            let lines: Vec<&[u8]> = batch
+                .as_bytes()
+                .split(|b| *b == b'\n')
+                .filter(|l| !l.is_empty())
+                .collect();
+            assert_eq!(lines.len(), sets.len());
+            assert_eq!(lines[0], &out[..]);
+        }
+    }
+
+    #[test]
+    fn test_write_csv() {
+        let addr = IpAddr::V4(Ipv4Addr::new(192, 168, 100, 1));
+        let mut parser = Parser::new();
+        let sets = parser.parse_netflow_packet(&PACKET_1, &addr).unwrap();
+
+        let mut writer = CsvWriter::from_names(&[
+            "source_ip",
+            "sa",
+            "destinationTransportPort",
+            "IPv6 Src Addr",
+            "protocol",
+        ])
+        .unwrap();
+        let mut out = Vec::new();
+        writer.write_header(&mut out).unwrap();
+        writer.write_records(&sets, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "source_ip,IPv4 Src Addr,L4 Dst port,IPv6 Src Addr,Protocol\n\
+             192.168.100.1,185.167.196.127,3784,,17\n"
+        );
+
+        writer.set_delimiter(b' ');
+        writer.set_format_options(FormatOptions {
+            enums: EnumStyle::Name,
+            keys: KeyStyle::Label,
+        });
+        let mut out = Vec::new();
+        writer.write_header(&mut out).unwrap();
+        writer.write_records(&sets, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "source_ip \"IPv4 Src Addr\" \"L4 Dst port\" \"IPv6 Src Addr\" Protocol\n\
+             192.168.100.1 185.167.196.127 3784  UDP\n"
+        );
+
+        let writer = CsvWriter::tsv(vec![Column::Field(7), Column::Field(11)]);
+        let mut out = Vec::new();
+        writer.write_records(&sets, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "49152\t3784\n");
+
+        assert!(CsvWriter::from_names(&["no such field"]).is_err());
+    }
+
+    #[test]
+    fn test_flow_times() {
+        let addr = IpAddr::V4(Ipv4Addr::new(192, 168, 100, 1));
+        let mut parser = Parser::new();
+        let sets = parser.parse_netflow_packet(&PACKET_1, &addr).unwrap();
+        assert_eq!(sets[0].flow_start_millis(), Some(1_512_080_649_760));
+        assert_eq!(sets[0].flow_end_millis(), Some(1_512_080_649_760));
+    }
+
+    #[cfg(feature = "arrow")]
+    #[test]
+    fn test_arrow_batches() {
+        use arrow_array::cast::AsArray;
+        use arrow_array::types::{TimestampMillisecondType, UInt64Type};
+        use arrow_array::Array;
+        use netflow_v9::{ArrowBatcher, ArrowConfig};
+
+        let addr = IpAddr::V4(Ipv4Addr::new(192, 168, 100, 1));
+        let mut parser = Parser::new();
+        let columns = vec![
+            Column::SourceIp,
+            Column::Field(8),
+            Column::Field(27),
+            Column::Field(1),
+            Column::Field(22),
+        ];
+        let mut batcher = ArrowBatcher::new(
+            columns,
+            ArrowConfig {
+                max_rows: 2,
+                ..Default::default()
+            },
+        );
+        assert_eq!(batcher.schema().field(1).name(), "ipv4_src_addr");
+
+        let sets = parser.parse_netflow_packet(&PACKET_1, &addr).unwrap();
+        assert!(batcher.push(&sets[0]).unwrap().is_none());
+        let sets = parser.parse_netflow_packet(&PACKET_2, &addr).unwrap();
+        let batch = batcher.push(&sets[0]).unwrap().unwrap();
+        assert!(batcher.is_empty());
+
+        assert_eq!(batch.num_rows(), 2);
+        let src = batch.column(1).as_string::<i32>();
+        assert_eq!(src.value(0), "185.167.196.127");
+        assert!(src.is_null(1));
+        let src6 = batch.column(2).as_string::<i32>();
+        assert!(src6.is_null(0));
+        assert_eq!(src6.value(1), "2a0b:ae00:121::4");
+        let bytes = batch.column(3).as_primitive::<UInt64Type>();
+        assert_eq!(bytes.values(), &[52, 72]);
+        let start = batch.column(4).as_primitive::<TimestampMillisecondType>();
+        assert_eq!(start.value(0), 1_512_080_649_760);
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn test_parquet_sink() {
+        use netflow_v9::{ParquetConfig, ParquetSink};
+        use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+        use std::time::{Duration, UNIX_EPOCH};
+
+        let directory = std::env::temp_dir().join("netflow_v9_parquet_test");
+        let _ = std::fs::remove_dir_all(&directory);
+        std::fs::create_dir_all(&directory).unwrap();
+
+        let addr = IpAddr::V4(Ipv4Addr::new(192, 168, 100, 1));
+        let mut parser = Parser::new();
+        let sets = parser.parse_netflow_packet(&PACKET_1, &addr).unwrap();
+
+        let mut sink = ParquetSink::new(
+            vec![Column::SourceIp, Column::Field(8), Column::Field(1)],
+            ParquetConfig {
+                directory: directory.clone(),
+                rotate_every: Duration::from_secs(60),
+                ..Default::default()
+            },
+        );
+        let t0 = UNIX_EPOCH + Duration::from_secs(6000);
+        sink.write_at(&sets[0], t0).unwrap();
+        sink.write_at(&sets[0], t0 + Duration::from_secs(59))
+            .unwrap();
+        // Next window goes to a new file
+        sink.write_at(&sets[0], t0 + Duration::from_secs(60))
+            .unwrap();
+        sink.close().unwrap();
+
+        // Existing files are kept, dropping the sink closes its
+        // files and the least recently written goes first beyond
+        // `max_open_files`
+        let mut sink = ParquetSink::new(
+            vec![Column::SourceIp, Column::Field(8), Column::Field(1)],
+            ParquetConfig {
+                directory: directory.clone(),
+                rotate_every: Duration::from_secs(60),
+                max_open_files: 1,
+                ..Default::default()
+            },
+        );
+        let other = IpAddr::V4(Ipv4Addr::new(192, 168, 100, 2));
+        let other_sets = Parser::new()
+            .parse_netflow_packet(&PACKET_1, &other)
+            .unwrap();
+        sink.write_at(&sets[0], t0).unwrap();
+        sink.write_at(&other_sets[0], t0).unwrap();
+        sink.write_at(&sets[0], t0).unwrap();
+        drop(sink);
+
+        let rows = |name: &str| {
+            let path = directory.join(format!("flows-{}.parquet", name));
+            let file = std::fs::File::open(path).unwrap();
+            ParquetRecordBatchReaderBuilder::try_new(file)
+                .unwrap()
+                .build()
+                .unwrap()
+                .map(|b| b.unwrap().num_rows())
+                .sum::<usize>()
+        };
+        assert_eq!(rows("192.168.100.1-6000"), 2);
+        assert_eq!(rows("192.168.100.1-6060"), 1);
+        assert_eq!(rows("192.168.100.1-6000-1"), 1);
+        assert_eq!(rows("192.168.100.2-6000"), 1);
+        assert_eq!(rows("192.168.100.1-6000-2"), 1);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn test_write_msgpack() {
+        let addr = IpAddr::V4(Ipv4Addr::new(192, 168, 100, 1));
+        let mut parser = Parser::new();
+        let sets = parser.parse_netflow_packet(&PACKET_1, &addr).unwrap();
+
+        let packed = sets[0].to_msgpack().unwrap();
+        assert!(packed.len() * 2 < sets[0].to_json().len());
+        // Source address as a 4 byte bin
+        let src = [0x08, 0xc4, 0x04, 185, 167, 196, 127];
+        assert!(packed.windows(src.len()).any(|w| w == src));
+
+        let mut out = Vec::new();
+        sets[0].write_msgpack(&mut out).unwrap();
+        assert_eq!(out.len(), packed.len());
+    }
+
+    #[cfg(feature = "cbor")]
+    #[test]
+    fn test_write_cbor() {
+        use ciborium::value::Value;
+
+        let addr = IpAddr::V4(Ipv4Addr::new(192, 168, 100, 1));
+        let mut parser = Parser::new();
+        let sets = parser.parse_netflow_packet(&PACKET_1, &addr).unwrap();
+
+        let cbor = sets[0].to_cbor().unwrap();
+        let value: Value = ciborium::de::from_reader(&cbor[..]).unwrap();
+        let map = value.as_map().unwrap();
+        let get = |map: &Vec<(Value, Value)>, key: Value| {
+            map.iter().find(|(k, _)| *k == key).unwrap().1.clone()
+        };
+        assert_eq!(
+            get(map, Value::from("source_ip")),
+            Value::Bytes(vec![192, 168, 100, 1])
+        );
+        let records = get(map, Value::from("records"));
+        let records = records.as_map().unwrap();
+        assert_eq!(
+            get(records, Value::from(8)),
+            Value::Bytes(vec![185, 167, 196, 127])
+        );
+        assert_eq!(get(records, Value::from(1)), Value::from(52));
+        assert_eq!(
+            get(records, Value::from(22)),
+            Value::from(1_512_080_649_760u64)
+        );
+    }
+
+    #[cfg(feature = "protobuf")]
+    #[test]
+    fn test_goflow_messages() {
+        use netflow_v9::{FlowMessage, FlowType};
+        use std::time::{Duration, UNIX_EPOCH};
+
+        let addr = IpAddr::V4(Ipv4Addr::new(192, 168, 100, 1));
+        let mut parser = Parser::new();
+        let received = UNIX_EPOCH + Duration::from_secs(1_512_080_650);
+
+        let mut stream = Vec::new();
+        for packet in [&PACKET_1[..], &PACKET_2[..]].iter() {
+            let sets = parser.parse_netflow_packet(packet, &addr).unwrap();
+            let msg = FlowMessage::from_flowset(&sets[0], received);
+            msg.write_length_delimited(&mut stream).unwrap();
+        }
+
+        let mut reader = &stream[..];
+        let msg = FlowMessage::read_length_delimited(&mut reader)
+            .unwrap()
+            .unwrap();
+        assert_eq!(msg.r#type, FlowType::NetflowV9 as i32);
+        assert_eq!(msg.sequence_num, 0x0001_efb7);
+        assert_eq!(msg.sampler_address, vec![192, 168, 100, 1]);
+        assert_eq!(msg.src_addr, vec![185, 167, 196, 127]);
+        assert_eq!(msg.etype, 0x0800);
+        assert_eq!(msg.proto, 17);
+        assert_eq!(msg.bytes, 52);
+        assert_eq!(msg.dst_port, 3784);
+        assert_eq!(msg.src_as, 207_044);
+        assert_eq!(msg.time_flow_start_ns, 1_512_080_649_760_000_000);
+        assert_eq!(msg.time_received_ns, 1_512_080_650_000_000_000);
+
+        let msg = FlowMessage::read_length_delimited(&mut reader)
+            .unwrap()
+            .unwrap();
+        assert_eq!(msg.etype, 0x86dd);
+        assert_eq!(msg.src_addr.len(), 16);
+        assert_eq!(msg.src_net, 128);
+        assert!(FlowMessage::read_length_delimited(&mut reader)
+            .unwrap()
+            .is_none());
+
+        // A length of 1 GiB is refused without allocating it
+        let huge: &[u8] = &[0x80, 0x80, 0x80, 0x80, 0x04];
+        let e = FlowMessage::read_length_delimited(huge).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_ecs() {
+        let addr = IpAddr::V4(Ipv4Addr::new(192, 168, 100, 1));
+        let mut parser = Parser::new();
+        let sets = parser.parse_netflow_packet(&PACKET_1, &addr).unwrap();
+        let doc = sets[0].to_ecs();
+
+        assert_eq!(doc["source"]["ip"], "185.167.196.127");
+        assert_eq!(doc["source"]["port"], 49152);
+        assert_eq!(doc["source"]["as"]["number"], 207_044);
+        assert_eq!(doc["destination"]["ip"], "185.167.196.126");
+        assert_eq!(doc["destination"]["port"], 3784);
+        assert_eq!(doc["network"]["transport"], "udp");
+        assert_eq!(doc["network"]["iana_number"], "17");
+        assert_eq!(doc["network"]["type"], "ipv4");
+        assert_eq!(doc["network"]["bytes"], 52);
+        assert_eq!(doc["network"]["packets"], 1);
+        assert_eq!(doc["network"]["direction"], "egress");
+        assert_eq!(doc["observer"]["ip"], "192.168.100.1");
+        assert_eq!(doc["observer"]["egress"]["interface"]["id"], "276");
+        assert_eq!(doc["event"]["start"], "2017-11-30T22:24:09.760Z");
+        assert_eq!(doc["event"]["duration"], 0);
+        assert_eq!(doc["netflow"]["ip_next_hop_ipv4_address"], "0.0.0.0");
+        assert_eq!(doc["netflow"]["forwarding_status"], 66);
+        assert_eq!(doc["netflow"]["exporter"]["source_id"], 0);
+
+        let sets = parser.parse_netflow_packet(&PACKET_2, &addr).unwrap();
+        let doc = sets[0].to_ecs();
+        assert_eq!(doc["source"]["ip"], "2a0b:ae00:121::4");
+        assert_eq!(doc["network"]["type"], "ipv6");
+        assert_eq!(doc["netflow"]["source_ipv6_prefix_length"], 128);
+    }
+
+    #[test]
+    fn test_influx_lines() {
+        let addr = IpAddr::V4(Ipv4Addr::new(192, 168, 100, 1));
+        let mut parser = Parser::new();
+        let sets = parser.parse_netflow_packet(&PACKET_1, &addr).unwrap();
+
+        let mut writer = InfluxWriter::new(InfluxConfig::default());
+        let mut out = Vec::new();
+        writer.write_record(&sets[0], &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "netflow,exporter=192.168.100.1,input_snmp=0,protocol=UDP \
+             inbytes=52i,inpackets=1i 1512080649760000000\n"
+        );
+
+        let mut writer = InfluxWriter::new(InfluxConfig {
+            measurement: "if traffic".to_owned(),
+            tags: vec![Column::Field(14)],
+            keys: KeyStyle::Label,
+            aggregate: Some(std::time::Duration::from_secs(60)),
+            ..Default::default()
+        });
+        let mut out = Vec::new();
+        writer.write_record(&sets[0], &mut out).unwrap();
+        writer.write_record(&sets[0], &mut out).unwrap();
+        assert!(out.is_empty());
+        writer.flush_expired(1_512_080_649_760, &mut out).unwrap();
+        assert!(out.is_empty());
+        writer.flush_expired(1_512_080_700_000, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "if\\ traffic,Output\\ SNMP=276 InBytes=104i,InPackets=2i \
+             1512080640000000000\n"
+        );
+
+        // Intervals too long to end don't overflow
+        let mut writer = InfluxWriter::new(InfluxConfig {
+            aggregate: Some(std::time::Duration::MAX),
+            ..Default::default()
+        });
+        let mut out = Vec::new();
+        writer.write_record(&sets[0], &mut out).unwrap();
+        writer.flush_expired(u64::MAX - 1, &mut out).unwrap();
+        assert!(out.is_empty());
+        writer.flush(&mut out).unwrap();
+        assert!(String::from_utf8(out).unwrap().ends_with(" 0\n"));
+    }
+
+    #[test]
+    fn test_text_format() {
+        let addr = IpAddr::V4(Ipv4Addr::new(192, 168, 100, 1));
+        let mut parser = Parser::new();
+        let sets = parser.parse_netflow_packet(&PACKET_1, &addr).unwrap();
+
+        let format = TextFormat::line();
+        assert_eq!(
+            format.header(),
+            "Date first seen          Duration Proto Src IP Addr:Port         \
+             Dst IP Addr:Port       Packets    Bytes Flows"
+        );
+        assert_eq!(
+            format.format(&sets[0]),
+            "2017-11-30 22:24:09.760     0.000 UDP   185.167.196.127:49152 -> \
+             185.167.196.126:3784         1       52     1"
+        );
+        assert_eq!(TextFormat::from_name("line"), Ok(format));
+
+        let format = TextFormat::from_name("extended").unwrap();
+        assert!(format.format(&sets[0]).ends_with(
+            "...... 192        1       52        0        0     52     1"
+        ));
+
+        let format = TextFormat::from_name(
+            "fmt:%ra %sn %{protocol} %{sourceIPv6Address} 100%%",
+        )
+        .unwrap();
+        let mut out = Vec::new();
+        format.write_header(&mut out).unwrap();
+        format.write_record(&sets[0], &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "Router IP       Src Network        Protocol IPv6 Src Addr\n\
+             192.168.100.1   185.167.196.127/32 UDP - 100%\n"
+        );
+
+        // Rates of 8 byte counters saturate rather than overflow
+        let mut packet =
+            template_packet(300, &[(1, 8), (2, 8), (21, 4), (22, 4)]);
+        packet[3] = 2;
+        for value in [300u16, 28] {
+            packet.extend_from_slice(&value.to_be_bytes());
+        }
+        packet.extend_from_slice(&u64::MAX.to_be_bytes());
+        packet.extend_from_slice(&u64::MAX.to_be_bytes());
+        packet.extend_from_slice(&1000u32.to_be_bytes());
+        packet.extend_from_slice(&999u32.to_be_bytes());
+        let sets = Parser::new().parse_netflow_packet(&packet, &addr).unwrap();
+        let format = TextFormat::from_name("fmt:%bps %pps").unwrap();
+        assert_eq!(
+            format.format(&sets[0]),
+            "18446744073709551615 18446744073709551615"
+        );
+
+        assert!(TextFormat::from_name("fmt:%xyz").is_err());
+        assert!(TextFormat::from_name("fmt:%{nope}").is_err());
+        assert!(TextFormat::from_name("short").is_err());
+    }
+
+    #[test]
+    fn test_owned_record_json() {
+        let addr = IpAddr::V4(Ipv4Addr::new(192, 168, 100, 1));
+        let mut parser = Parser::new();
+        let sets = parser.parse_netflow_packet(&PACKET_1, &addr).unwrap();
+
+        let record = OwnedFlowRecord::from(&sets[0]);
+        assert_eq!(record.source_ip(), Some(addr));
+        assert_eq!(record.get(8), Some(&[185, 167, 196, 127][..]));
+        assert_eq!(record.get(300), None);
+
+        let json = serde_json::to_string(&record).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["source_ip"], "192.168.100.1");
+        assert_eq!(value["fields"]["8"], "b9a7c47f");
+
+        let read: OwnedFlowRecord = serde_json::from_str(&json).unwrap();
+        assert_eq!(read, record);
+        // The same output code applies to records read back
+        assert_eq!(
+            serde_json::to_value(read.flowset()).unwrap(),
+            serde_json::to_value(&sets[0]).unwrap()
+        );
+        assert_eq!(read.flowset().flow_end_millis(), sets[0].flow_end_millis());
+
+        assert!(serde_json::from_str::<OwnedFlowRecord>(
+            &json.replace("b9a7c47f", "b9a7c47")
+        )
+        .is_err());
+    }
+
+    #[cfg(any(feature = "msgpack", feature = "cbor"))]
+    #[test]
+    fn test_owned_record_binary() {
+        let addr = IpAddr::V6("2001:db8::1".parse().unwrap());
+        let mut parser = Parser::new();
+        let sets = parser.parse_netflow_packet(&PACKET_1, &addr).unwrap();
+        let record = OwnedFlowRecord::from(&sets[0]);
+
+        #[cfg(feature = "msgpack")]
+        {
+            let packed = rmp_serde::to_vec(&record).unwrap();
+            let read: OwnedFlowRecord = rmp_serde::from_slice(&packed).unwrap();
+            assert_eq!(read, record);
+        }
+        #[cfg(feature = "cbor")]
+        {
+            let mut cbor = Vec::new();
+            ciborium::ser::into_writer(&record, &mut cbor).unwrap();
+            let read: OwnedFlowRecord =
+                ciborium::de::from_reader(&cbor[..]).unwrap();
+            assert_eq!(read, record);
+        }
+    }
+
+    #[test]
+    fn test_into_owned() {
+        fn assert_send<T: Send + 'static>(_: &T) {}
+
+        let mut parser = Parser::new();
+        let records: Vec<OwnedFlowRecord> = {
+            let packet = PACKET_1.to_vec();
+            let addr = IpAddr::V4(Ipv4Addr::new(192, 168, 100, 1));
+            let sets = parser.parse_netflow_packet(&packet, &addr).unwrap();
+            sets.into_iter().map(|s| s.into_owned()).collect()
+        };
+        assert_send(&records);
+
+        let worker = std::thread::spawn(move || {
+            records
+                .iter()
+                .map(|r| (r.get(1).unwrap().to_vec(), r.flowset().to_json()))
+                .collect::<Vec<_>>()
+        });
+        let out = worker.join().unwrap();
+        assert_eq!(out.len(), 1);
+        assert_eq!(out[0].0, vec![0, 0, 0, 52]);
+        assert!(out[0].1.contains("\"IPv4 Src Addr\":\"185.167.196.127\""));
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_flow_stream() {
+        use self::netflow_v9::{
+            Backpressure, FlowStream, ParseError, PendingLimits, StreamConfig,
+        };
+        use futures_core::Stream;
+        use std::pin::Pin;
+
+        async fn next(
+            stream: &mut FlowStream,
+        ) -> Option<<FlowStream as Stream>::Item> {
+            std::future::poll_fn(|cx| Pin::new(&mut *stream).poll_next(cx))
+                .await
+        }
+
+        let mut stream =
+            FlowStream::bind("127.0.0.1:0", StreamConfig::default())
+                .await
+                .unwrap();
+        let target = stream.local_addr();
+        let sender = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        sender.send_to(&PACKET_1, target).unwrap();
+        sender.send_to(&[0, 5], target).unwrap();
+
+        let received = next(&mut stream).await.unwrap().unwrap();
+        assert_eq!(received.exporter, sender.local_addr().unwrap());
+        assert_eq!(
+            received.record.source_ip(),
+            Some(IpAddr::V4(Ipv4Addr::LOCALHOST))
+        );
+        assert_eq!(received.record.get(2), Some(&[0, 0, 0, 1][..]));
+        match next(&mut stream).await.unwrap() {
+            Err(ParseError::Packet { exporter, .. }) => {
+                assert_eq!(exporter, sender.local_addr().unwrap())
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let mut stream = FlowStream::bind(
+            "127.0.0.1:0",
+            StreamConfig {
+                capacity: 1,
+                backpressure: Backpressure::Drop,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        let target = stream.local_addr();
+        for _ in 0..3 {
+            sender.send_to(&PACKET_1, target).unwrap();
+        }
+        while stream.packets() < 3 {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(stream.dropped(), 2);
+        assert!(next(&mut stream).await.unwrap().is_ok());
+
+        // Flowsets kept until their template arrives are streamed
+        let mut parser = Parser::new();
+        parser.set_pending_limits(PendingLimits {
+            max_bytes: 4096,
+            max_age: std::time::Duration::from_secs(60),
+        });
+        let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut stream =
+            FlowStream::with_parser(socket, StreamConfig::default(), parser)
+                .unwrap();
+        let target = stream.local_addr();
+        sender.send_to(&data_only_packet(), target).unwrap();
+        sender.send_to(&PACKET_1, target).unwrap();
+        for _ in 0..2 {
+            let received = next(&mut stream).await.unwrap().unwrap();
+            assert_eq!(received.record.get(2), Some(&[0, 0, 0, 1][..]));
+        }
+    }
+
+    /// The data flowset of `PACKET_1`, without its templates
+    fn data_only_packet() -> Vec<u8> {
+        let mut packet = PACKET_1[..20].to_vec();
+        packet[3] = 1;
+        packet.extend_from_slice(&PACKET_1[164..]);
+        packet
+    }
+
+    /// A packet with the header of `PACKET_1` and a single
+    /// template, of `(field, length)` pairs
+    fn template_packet(template_id: u16, fields: &[(u16, u16)]) -> Vec<u8> {
+        let mut packet = PACKET_1[..20].to_vec();
+        packet[3] = 1;
+        let length = 8 + 4 * fields.len() as u16;
+        for value in [0, length, template_id, fields.len() as u16] {
+            packet.extend_from_slice(&value.to_be_bytes());
+        }
+        for (field, len) in fields {
+            packet.extend_from_slice(&field.to_be_bytes());
+            packet.extend_from_slice(&len.to_be_bytes());
+        }
+        packet
+    }
+
+    /// Set the uptime and sequence number of a packet
+    fn set_clock(packet: &mut [u8], sys_uptime: u32, sequence: u32) {
+        packet[4..8].copy_from_slice(&sys_uptime.to_be_bytes());
+        packet[12..16].copy_from_slice(&sequence.to_be_bytes());
+    }
+
+    #[test]
+    fn test_shared_template_cache() {
+        let addr = IpAddr::V4(Ipv4Addr::new(192, 168, 100, 1));
+        let data = data_only_packet();
+
+        let cache = SharedTemplateCache::new();
+        let mut first = Parser::with_shared_cache(cache.clone());
+        let mut second = Parser::with_shared_cache(cache.clone());
+        assert!(second
+            .parse_netflow_packet(&data, &addr)
+            .unwrap()
+            .is_empty());
+
+        assert_eq!(
+            first.parse_netflow_packet(&PACKET_1, &addr).unwrap().len(),
+            1
+        );
+        assert_eq!(cache.len(), 2);
+        let sets = second.parse_netflow_packet(&data, &addr).unwrap();
+        assert_eq!(sets.len(), 1);
+        assert_eq!(
+            serde_json::to_value(&sets[0]).unwrap(),
+            serde_json::to_value(
+                &first.parse_netflow_packet(&data, &addr).unwrap()[0]
+            )
+            .unwrap()
+        );
+
+        // Templates are scoped by exporter
+        let other = IpAddr::V4(Ipv4Addr::new(192, 168, 100, 2));
+        assert!(second
+            .parse_netflow_packet(&data, &other)
+            .unwrap()
+            .is_empty());
+
+        let worker = std::thread::spawn(move || {
+            let mut parser = Parser::with_shared_cache(cache);
+            parser.parse_netflow_packet(&data, &addr).unwrap().len()
+        });
+        assert_eq!(worker.join().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_template_store() {
+        use self::netflow_v9::{
+            MemoryTemplateStore, TemplateKey, TemplateKind,
+        };
+        use std::time::{Duration, SystemTime};
+
+        let addr = IpAddr::V4(Ipv4Addr::new(192, 168, 100, 1));
+        let store: Box<dyn TemplateStore + Send> =
+            Box::new(MemoryTemplateStore::new());
+        let mut parser = Parser::with_store(store);
+        parser.parse_netflow_packet(&PACKET_1, &addr).unwrap();
+
+        let mut keys: Vec<TemplateKey> =
+            parser.templates().iter().map(|(k, _)| k).collect();
+        keys.sort();
+        assert_eq!(
+            keys.iter().map(|k| k.template_id).collect::<Vec<_>>(),
+            vec![256, 257]
+        );
+        assert!(keys.iter().all(|k| k.exporter == addr && k.source_id == 0));
+
+        let data_key = keys[1];
+        let template = parser.templates_mut().lookup(&data_key).unwrap();
+        assert_eq!(template.kind, TemplateKind::Data);
+        assert_eq!(template.fields.len(), 24);
+        assert_eq!(template.fields[0].field, 8);
+        assert_eq!(template.record_len(), 62);
+        let options = parser.templates_mut().lookup(&keys[0]).unwrap();
+        assert_eq!(options.kind, TemplateKind::Options { scope_fields: 1 });
+
+        // Without its template the data flowset is skipped
+        let data = data_only_packet();
+        assert!(parser.templates_mut().withdraw(&data_key).is_some());
+        assert!(parser
+            .parse_netflow_packet(&data, &addr)
+            .unwrap()
+            .is_empty());
+        parser.templates_mut().insert(data_key, template);
+        assert_eq!(parser.parse_netflow_packet(&data, &addr).unwrap().len(), 1);
+
+        let store = parser.templates_mut();
+        assert_eq!(
+            store.expire(SystemTime::now() - Duration::from_secs(60)),
+            0
+        );
+        assert_eq!(store.expire(SystemTime::now() + Duration::from_secs(1)), 2);
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn test_template_snapshot() {
+        use self::netflow_v9::{
+            Template, TemplateKey, TemplateKind, TemplateSnapshot,
+        };
+        use std::sync::Arc;
+        use std::time::{Duration, SystemTime};
+
+        let addr = IpAddr::V4(Ipv4Addr::new(192, 168, 100, 1));
+        let mut parser = Parser::new();
+        parser.parse_netflow_packet(&PACKET_1, &addr).unwrap();
+        let path = std::env::temp_dir()
+            .join(format!("netflow-templates-{}.json", std::process::id()));
+        parser.save_templates(&path).unwrap();
+
+        // A new parser decodes data flowsets right away
+        let data = data_only_packet();
+        let mut restarted = Parser::new();
+        assert!(restarted
+            .parse_netflow_packet(&data, &addr)
+            .unwrap()
+            .is_empty());
+        assert_eq!(restarted.load_templates(&path, None).unwrap(), 2);
+        let sets = restarted.parse_netflow_packet(&data, &addr).unwrap();
+        assert_eq!(sets.len(), 1);
+        let expected = parser.parse_netflow_packet(&data, &addr).unwrap();
+        assert_eq!(
+            serde_json::to_value(&sets[0]).unwrap(),
+            serde_json::to_value(&expected[0]).unwrap()
+        );
+
+        // Stale templates are not restored
+        let mut snapshot = TemplateSnapshot::load(&path).unwrap();
+        assert_eq!(snapshot.len(), 2);
+        assert!(snapshot.taken() <= SystemTime::now());
+        let key = TemplateKey {
+            exporter: addr,
+            source_id: 1,
+            template_id: 300,
+        };
+        let mut store = parser.templates().clone();
+        store.insert(
+            key,
+            Arc::new(Template {
+                kind: TemplateKind::Data,
+                fields: Vec::new(),
+                updated: SystemTime::now() - Duration::from_secs(7200),
+            }),
+        );
+        snapshot = TemplateSnapshot::capture(&store);
+        let mut fresh = Parser::new();
+        let max_age = Some(Duration::from_secs(3600));
+        assert_eq!(snapshot.restore(fresh.templates_mut(), max_age), 2);
+        assert!(fresh.templates_mut().lookup(&key).is_none());
+        let mut buffer = Vec::new();
+        snapshot.write(&mut buffer).unwrap();
+        assert_eq!(TemplateSnapshot::read(&buffer[..]).unwrap(), snapshot);
+
+        // Snapshots of another version are rejected
+        let mut json: serde_json::Value =
+            serde_json::from_slice(&buffer).unwrap();
+        json["version"] = 99.into();
+        let buffer = serde_json::to_vec(&json).unwrap();
+        assert!(TemplateSnapshot::read(&buffer[..]).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_static_templates() {
+        use self::netflow_v9::{
+            StaticTemplate, StaticTemplatePolicy, TemplateEvent, TemplateKey,
+        };
+
+        let static_template: StaticTemplate =
+            serde_json::from_value(serde_json::json!({
+                "exporter": "192.0.2.1",
+                "template_id": 256,
+                "fields": [
+                    { "field": "sourceIPv4Address", "len": 4 },
+                    { "field": "l4_src_port", "len": 2 },
+                    { "field": "sa", "len": 4 },
+                    { "field": 1, "len": 4 },
+                ],
+            }))
+            .unwrap();
+        assert_eq!(static_template.source_id, 0);
+        assert_eq!(
+            static_template
+                .fields
+                .iter()
+                .map(|f| f.field)
+                .collect::<Vec<_>>(),
+            vec![8, 7, 8, 1]
+        );
+        assert!(serde_json::from_value::<StaticTemplate>(serde_json::json!({
+            "exporter": "192.0.2.1",
+            "template_id": 256,
+            "fields": [{ "field": "noSuchField", "len": 4 }],
+        }))
+        .is_err());
+
+        // Data flowsets are decoded with the static template
+        // until the exporter sends its own
+        let addr = IpAddr::V4(Ipv4Addr::new(192, 168, 100, 1));
+        let key = TemplateKey {
+            exporter: addr,
+            source_id: 0,
+            template_id: 257,
+        };
+        let mut learned = Parser::new();
+        learned.parse_netflow_packet(&PACKET_1, &addr).unwrap();
+        let template = learned.templates_mut().lookup(&key).unwrap();
+        let mut defined = StaticTemplate {
+            exporter: addr,
+            source_id: 0,
+            template_id: 257,
+            scope_fields: None,
+            fields: template.fields.clone(),
+        };
+        let data = data_only_packet();
+        let mut parser = Parser::new();
+        parser.add_static_template(&defined);
+        assert_eq!(parser.parse_netflow_packet(&data, &addr).unwrap().len(), 1);
+        parser.parse_netflow_packet(&PACKET_1, &addr).unwrap();
+        assert_eq!(parser.drain_events().count(), 0);
+
+        // Received templates that disagree are reported, and
+        // used unless the policy is to verify
+        defined.fields.pop();
+        for policy in
+            [StaticTemplatePolicy::Override, StaticTemplatePolicy::Verify]
+        {
+            let overridden = policy == StaticTemplatePolicy::Override;
+            let mut parser = Parser::new();
+            parser.set_static_policy(policy);
+            parser.add_static_template(&defined);
+            parser.parse_netflow_packet(&PACKET_1, &addr).unwrap();
+            assert_eq!(
+                parser.drain_events().collect::<Vec<_>>(),
+                vec![TemplateEvent::StaticMismatch { key, overridden }]
+            );
+            assert_eq!(
+                parser.templates_mut().lookup(&key).is_some(),
+                overridden
+            );
+
+            // Refreshes are not reported again
+            parser.parse_netflow_packet(&PACKET_1, &addr).unwrap();
+            assert_eq!(parser.drain_events().count(), 0);
+        }
+
+        // The static definition is not used once overridden
+        let mut parser = Parser::new();
+        parser.add_static_template(&defined);
+        parser.parse_netflow_packet(&PACKET_1, &addr).unwrap();
+        parser.templates_mut().withdraw(&key);
+        assert!(parser
+            .parse_netflow_packet(&data, &addr)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_pending_flowsets() {
+        use self::netflow_v9::{PendingLimits, PendingStats};
+        use std::time::Duration;
+
+        let addr = IpAddr::V4(Ipv4Addr::new(192, 168, 100, 1));
+        let data = data_only_packet();
+
+        // Dropped by default
+        let mut parser = Parser::new();
+        assert!(parser
+            .parse_netflow_packet(&data, &addr)
+            .unwrap()
+            .is_empty());
+        parser.parse_netflow_packet(&PACKET_1, &addr).unwrap();
+        assert!(parser.take_replayed().is_empty());
+        assert_eq!(parser.pending_stats(), PendingStats::default());
+
+        // Decoded once the template arrives
+        let mut parser = Parser::new();
+        parser.set_pending_limits(PendingLimits::default());
+        assert!(parser
+            .parse_netflow_packet(&data, &addr)
+            .unwrap()
+            .is_empty());
+        let stats = parser.pending_stats();
+        assert_eq!((stats.pending, stats.bytes), (1, 62));
+        let sets = parser.parse_netflow_packet(&PACKET_1, &addr).unwrap();
+        let replayed = parser.take_replayed();
+        assert_eq!(replayed.len(), 1);
+        assert_eq!(replayed[0], OwnedFlowRecord::from(&sets[0]));
+        assert_eq!(replayed[0].source_ip(), Some(addr));
+        assert!(parser.take_replayed().is_empty());
+        let stats = parser.pending_stats();
+        assert_eq!((stats.pending, stats.bytes, stats.replayed), (0, 0, 1));
+
+        // Limited in size and age
+        let mut parser = Parser::new();
+        parser.set_pending_limits(PendingLimits {
+            max_bytes: 100,
+            ..PendingLimits::default()
+        });
+        parser.parse_netflow_packet(&data, &addr).unwrap();
+        parser.parse_netflow_packet(&data, &addr).unwrap();
+        let stats = parser.pending_stats();
+        assert_eq!((stats.pending, stats.dropped), (1, 1));
+
+        // Decoded records not taken count against the size limit
+        parser.parse_netflow_packet(&PACKET_1, &addr).unwrap();
+        assert_eq!(parser.pending_stats().unclaimed, 62);
+        let other = IpAddr::V4(Ipv4Addr::new(192, 168, 100, 2));
+        parser.parse_netflow_packet(&data, &other).unwrap();
+        assert!(parser.take_replayed().is_empty());
+        let stats = parser.pending_stats();
+        assert_eq!((stats.pending, stats.unclaimed, stats.dropped), (1, 0, 2));
+
+        let mut parser = Parser::new();
+        parser.set_pending_limits(PendingLimits {
+            max_age: Duration::ZERO,
+            ..PendingLimits::default()
+        });
+        parser.parse_netflow_packet(&data, &addr).unwrap();
+        parser.parse_netflow_packet(&PACKET_1, &addr).unwrap();
+        assert!(parser.take_replayed().is_empty());
+        let stats = parser.pending_stats();
+        assert_eq!((stats.pending, stats.expired), (0, 1));
+    }
+
+    #[test]
+    fn test_template_lifecycle() {
+        use self::netflow_v9::{TemplateEvent, TemplateKey};
+        use std::time::Duration;
+
+        let addr = IpAddr::V4(Ipv4Addr::new(192, 168, 100, 1));
+        let key = TemplateKey {
+            exporter: addr,
+            source_id: 0,
+            template_id: 257,
+        };
+        let mut parser = Parser::new();
+        parser.parse_netflow_packet(&PACKET_1, &addr).unwrap();
+        parser.parse_netflow_packet(&PACKET_1, &addr).unwrap();
+        assert_eq!(parser.drain_events().count(), 0);
+
+        // Redefinition and withdrawal
+        let packet = template_packet(257, &[(8, 4)]);
+        parser.parse_netflow_packet(&packet, &addr).unwrap();
+        assert_eq!(
+            parser.drain_events().collect::<Vec<_>>(),
+            vec![TemplateEvent::Redefined { key }]
+        );
+        let packet = template_packet(257, &[]);
+        parser.parse_netflow_packet(&packet, &addr).unwrap();
+        assert_eq!(
+            parser.drain_events().collect::<Vec<_>>(),
+            vec![TemplateEvent::Withdrawn { key }]
+        );
+        assert!(parser.templates_mut().lookup(&key).is_none());
+
+        // Reordered packets are not restarts, going back further
+        // in uptime or sequence number is
+        let (uptime, sequence) = (0x637626ee, 0x0001efb7);
+        parser.parse_netflow_packet(&PACKET_1, &addr).unwrap();
+        let mut data = data_only_packet();
+        set_clock(&mut data, uptime - 1000, sequence - 1);
+        assert_eq!(parser.parse_netflow_packet(&data, &addr).unwrap().len(), 1);
+        assert_eq!(parser.drain_events().count(), 0);
+        for (uptime, sequence) in [(1000, sequence + 1), (uptime + 1, 0)] {
+            parser.parse_netflow_packet(&PACKET_1, &addr).unwrap();
+            set_clock(&mut data, uptime, sequence);
+            assert!(parser
+                .parse_netflow_packet(&data, &addr)
+                .unwrap()
+                .is_empty());
+            assert_eq!(
+                parser.drain_events().collect::<Vec<_>>(),
+                vec![TemplateEvent::ExporterRestarted {
+                    exporter: addr,
+                    source_id: 0,
+                    flushed: 2,
+                }]
+            );
+            // Start over from the clock of `PACKET_1`
+            parser = Parser::new();
+        }
+
+        // Timeout
+        parser.set_template_timeout(Some(Duration::ZERO));
+        parser.parse_netflow_packet(&PACKET_1, &addr).unwrap();
+        assert!(parser
+            .parse_netflow_packet(&data_only_packet(), &addr)
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            parser.drain_events().collect::<Vec<_>>(),
+            vec![TemplateEvent::Expired { count: 2 }]
+        );
+    }
+
+    #[test]
+    fn test_template_limits() {
+        use self::netflow_v9::{
+            MemoryTemplateStore, TemplateEvent, TemplateKey, TemplateLimits,
+        };
+
+        let limits = TemplateLimits {
+            max_templates: Some(3),
+            max_templates_per_exporter: Some(2),
+            max_exporters: Some(2),
+            ..TemplateLimits::default()
+        };
+        let mut parser =
+            Parser::with_store(MemoryTemplateStore::with_limits(limits));
+        let a = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        let b = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+        let c = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 3));
+        let key = |exporter, template_id| TemplateKey {
+            exporter,
+            source_id: 0,
+            template_id,
+        };
+        let mut learn = |exporter, template_id| {
+            let packet = template_packet(template_id, &[(8, 4), (12, 4)]);
+            parser.parse_netflow_packet(&packet, &exporter).unwrap();
+        };
+        learn(a, 300);
+        learn(a, 301);
+        learn(a, 300);
+        learn(a, 302);
+        learn(b, 400);
+        learn(c, 500);
+        learn(b, 401);
+
+        // 301 went first as the least recently used of `a`, then
+        // 300 as the least recently used of all
+        let mut keys: Vec<TemplateKey> =
+            parser.templates().iter().map(|(k, _)| k).collect();
+        keys.sort();
+        assert_eq!(keys, vec![key(a, 302), key(b, 400), key(b, 401)]);
+        let stats = parser.templates().stats();
+        assert_eq!((stats.templates, stats.exporters), (3, 2));
+        assert_eq!((stats.evicted, stats.rejected), (2, 1));
+        let size = parser.templates_mut().lookup(&keys[0]).unwrap().size();
+        assert_eq!(stats.bytes, 3 * size);
+
+        // Byte limits, shared by the handles of a cache
+        let cache = SharedTemplateCache::with_limits(TemplateLimits {
+            max_bytes_per_exporter: Some(size),
+            ..TemplateLimits::default()
+        });
+        let mut first = Parser::with_shared_cache(cache.clone());
+        let mut second = Parser::with_shared_cache(cache.clone());
+        let packet = template_packet(300, &[(8, 4), (12, 4)]);
+        first.parse_netflow_packet(&packet, &a).unwrap();
+        let packet = template_packet(301, &[(8, 4), (12, 4)]);
+        second.parse_netflow_packet(&packet, &a).unwrap();
+        let packet = template_packet(302, &[(8, 4), (12, 4), (7, 2)]);
+        second.parse_netflow_packet(&packet, &b).unwrap();
+        assert!(first.templates_mut().lookup(&key(a, 300)).is_none());
+        assert!(first.templates_mut().lookup(&key(a, 301)).is_some());
+        let stats = cache.stats();
+        assert_eq!((stats.templates, stats.evicted, stats.rejected), (1, 1, 1));
+
+        // Lookups from the shared map count as uses
+        let cache = SharedTemplateCache::with_limits(TemplateLimits {
+            max_templates: Some(2),
+            ..TemplateLimits::default()
+        });
+        let mut first = Parser::with_shared_cache(cache.clone());
+        let mut second = Parser::with_shared_cache(cache.clone());
+        for template_id in [300, 301] {
+            let packet = template_packet(template_id, &[(8, 4), (12, 4)]);
+            first.parse_netflow_packet(&packet, &a).unwrap();
+        }
+        assert!(second.templates_mut().lookup(&key(a, 300)).is_some());
+        let packet = template_packet(302, &[(8, 4), (12, 4)]);
+        first.parse_netflow_packet(&packet, &a).unwrap();
+        assert!(second.templates_mut().lookup(&key(a, 300)).is_some());
+        assert!(second.templates_mut().lookup(&key(a, 301)).is_none());
+        assert!(second.templates_mut().lookup(&key(a, 302)).is_some());
+
+        // Exporters without templates don't keep restart detection
+        // from those with templates beyond the exporter limit
+        let mut parser = Parser::with_store(MemoryTemplateStore::with_limits(
+            TemplateLimits {
+                max_exporters: Some(1),
+                ..TemplateLimits::default()
+            },
+        ));
+        parser
+            .parse_netflow_packet(&data_only_packet(), &b)
+            .unwrap();
+        parser.parse_netflow_packet(&PACKET_1, &a).unwrap();
+        let mut data = data_only_packet();
+        set_clock(&mut data, 1000, 0);
+        assert!(parser.parse_netflow_packet(&data, &a).unwrap().is_empty());
+        assert_eq!(
+            parser.drain_events().collect::<Vec<_>>(),
+            vec![TemplateEvent::ExporterRestarted {
+                exporter: a,
+                source_id: 0,
+                flushed: 2,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_exporter_protection() {
+        use self::netflow_v9::{Cidr, ExporterAcl, TemplateEvent, TemplateKey};
+        use std::time::Duration;
+
+        let net: Cidr = "192.0.2.0/24".parse().unwrap();
+        assert!(net.contains(&"192.0.2.200".parse().unwrap()));
+        assert!(net.contains(&"::ffff:192.0.2.1".parse().unwrap()));
+        assert!(!net.contains(&"192.0.3.1".parse().unwrap()));
+        assert!(!net.contains(&"2001:db8::1".parse().unwrap()));
+        let net: Cidr = "2001:db8::/32".parse().unwrap();
+        assert!(net.contains(&"2001:db8:1::1".parse().unwrap()));
+        assert!("0.0.0.0/0"
+            .parse::<Cidr>()
+            .unwrap()
+            .contains(&"198.51.100.1".parse().unwrap()));
+        assert_eq!(
+            "192.0.2.1".parse::<Cidr>().unwrap().to_string(),
+            "192.0.2.1/32"
+        );
+        assert!("192.0.2.0/33".parse::<Cidr>().is_err());
+        // Host bits are cleared and mapped IPv4 networks are
+        // IPv4 networks
+        assert_eq!(
+            "10.0.0.5/24".parse::<Cidr>().unwrap().to_string(),
+            "10.0.0.0/24"
+        );
+        let net: Cidr = "::ffff:10.0.0.0/104".parse().unwrap();
+        assert_eq!(net.to_string(), "10.0.0.0/8");
+        assert!(net.contains(&"10.1.2.3".parse().unwrap()));
+        assert!(net.contains(&"::ffff:10.1.2.3".parse().unwrap()));
+        assert!(!net.contains(&"11.0.0.1".parse().unwrap()));
+
+        let acl: ExporterAcl = serde_json::from_value(serde_json::json!({
+            "allow": ["192.0.2.0/24"],
+            "deny": ["192.0.2.66"],
+        }))
+        .unwrap();
+        let trusted = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        let denied = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 66));
+        let other = IpAddr::V4(Ipv4Addr::new(198, 51, 100, 1));
+        assert!(acl.allows(&trusted));
+        assert!(!acl.allows(&denied));
+        assert!(!acl.allows(&other));
+        assert!(ExporterAcl::default().allows(&other));
+
+        let mut parser = Parser::new();
+        parser.set_exporter_acl(acl);
+        assert_eq!(
+            parser
+                .parse_netflow_packet(&PACKET_1, &trusted)
+                .unwrap()
+                .len(),
+            1
+        );
+        assert!(parser.parse_netflow_packet(&PACKET_1, &denied).is_err());
+        assert!(parser.parse_netflow_packet(&PACKET_1, &other).is_err());
+
+        // Pinned templates stay as first received
+        let key = TemplateKey {
+            exporter: trusted,
+            source_id: 0,
+            template_id: 257,
+        };
+        let mut parser = Parser::new();
+        parser.pin_exporters(vec!["192.0.2.1".parse().unwrap()]);
+        parser.parse_netflow_packet(&PACKET_1, &trusted).unwrap();
+        for fields in [&[(8, 4)][..], &[]] {
+            let packet = template_packet(257, fields);
+            parser.parse_netflow_packet(&packet, &trusted).unwrap();
+            let events: Vec<_> = parser.drain_events().collect();
+            assert_eq!(events, vec![TemplateEvent::PinnedRedefinition { key }]);
+            assert!(events[0].is_alert());
+        }
+        let mut data = data_only_packet();
+        set_clock(&mut data, 1000, 0);
+        assert_eq!(
+            parser.parse_netflow_packet(&data, &trusted).unwrap().len(),
+            1
+        );
+
+        // Redefinitions beyond the rate are alerted on, once
+        let mut parser = Parser::new();
+        parser.set_redefinition_limit(2, Duration::from_secs(60));
+        let alerts = (0..6)
+            .flat_map(|i| {
+                let packet = template_packet(300, &[(8, 4 + i % 2)]);
+                parser.parse_netflow_packet(&packet, &other).unwrap();
+                parser.drain_events().collect::<Vec<_>>()
+            })
+            .filter(TemplateEvent::is_alert)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            alerts,
+            vec![TemplateEvent::RedefinitionRate {
+                key: TemplateKey {
+                    exporter: other,
+                    source_id: 0,
+                    template_id: 300,
+                },
+                count: 3,
+                window: Duration::from_secs(60),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_invalid_flowset_length() {
+        let addr = IpAddr::V4(Ipv4Addr::new(192, 168, 100, 1));
+        for length in [0u16, 2, 0xffff] {
+            let mut data = data_only_packet();
+            data[22..24].copy_from_slice(&length.to_be_bytes());
+            let mut parser = Parser::new();
+            assert_eq!(
+                parser.parse_netflow_packet(&data, &addr).unwrap_err(),
+                "Invalid flowset length"
+            );
+        }
+
+        // Templates shorter than their header or their fields
+        let mut short = template_packet(256, &[(8, 4)]);
+        short[22..24].copy_from_slice(&2u16.to_be_bytes());
+        let mut overflow = template_packet(256, &[(8, 4)]);
+        overflow[26..28].copy_from_slice(&100u16.to_be_bytes());
+        for data in [short, overflow] {
+            let mut parser = Parser::new();
+            assert_eq!(
+                parser.parse_netflow_packet(&data, &addr).unwrap_err(),
+                "Failed to parse the template"
+            );
+        }
+
+        // Options templates shorter than their header or their fields
+        let mut short = PACKET_1[..20].to_vec();
+        short[3] = 1;
+        for value in [1u16, 4, 256, 4, 4] {
+            short.extend_from_slice(&value.to_be_bytes());
+        }
+        let mut overflow = PACKET_1[..20].to_vec();
+        overflow[3] = 1;
+        for value in [1u16, 18, 256, 4, 0xfffc, 1, 4, 8, 4] {
+            overflow.extend_from_slice(&value.to_be_bytes());
+        }
+        for data in [short, overflow] {
+            let mut parser = Parser::new();
+            assert_eq!(
+                parser.parse_netflow_packet(&data, &addr).unwrap_err(),
+                "Failed to parse the options template"
+            );
+        }
+    }
+
+    #[test]
+    fn test_empty_flowset_count() {
+        let addr = IpAddr::V4(Ipv4Addr::new(192, 168, 100, 1));
+        let mut data = PACKET_1[..20].to_vec();
+        data[3] = 0;
+        let mut parser = Parser::new();
+        assert!(parser
+            .parse_netflow_packet(&data, &addr)
+            .unwrap()
+            .is_empty());
+
+        // A count of zero stops before any flowset
+        let mut data = PACKET_1.to_vec();
+        data[3] = 0;
+        assert!(parser
+            .parse_netflow_packet(&data, &addr)
+            .unwrap()
+            .is_empty());
+        assert!(parser
+            .parse_netflow_packet(&data_only_packet(), &addr)
+            .unwrap()
+            .is_empty());
+    }
 }